@@ -2,6 +2,7 @@ mod config;
 mod debugger;
 mod draw_data;
 mod macros;
+mod save_file;
 mod time_source;
 mod ui;
 mod window;
@@ -10,7 +11,7 @@ use log::{error, info};
 use std::{fs::File, io::prelude::*};
 use super_rustycom_core::snes::Snes;
 
-use crate::{config::Config, debugger::Debugger, window::Window};
+use crate::{config::Config, debugger::Debugger, save_file::SaveFile, window::Window};
 
 fn unwrap<T, E>(result: Result<T, E>) -> T
 where
@@ -48,11 +49,13 @@ fn setup_logger() -> Result<(), fern::InitError> {
 }
 
 const HELP: &str = "\
---rom [FILE]      Sets the rom file to use, previous file used if not given
+--rom [FILE]           Sets the rom file to use, previous file used if not given
+--strict-ppu-access    Drops VRAM, OAM and CGRAM writes outside blanking like the hardware
 ";
 
 struct Args {
     rom: Option<String>,
+    strict_ppu_access: bool,
 }
 
 fn parse_args() -> Result<Args, pico_args::Error> {
@@ -64,6 +67,7 @@ fn parse_args() -> Result<Args, pico_args::Error> {
 
     let args = Args {
         rom: pargs.opt_value_from_str("--rom")?,
+        strict_ppu_access: pargs.contains("--strict-ppu-access"),
     };
 
     let remaining = pargs.finish();
@@ -102,11 +106,20 @@ fn main() {
     };
 
     // Init hardware
-    let snes = Snes::new(rom_bytes);
+    let mut snes = match Snes::new(rom_bytes) {
+        Ok(snes) => snes,
+        Err(why) => {
+            error!("Could not load {}: {}", config.rom_path, why);
+            eprintln!("Could not load {}: {}", config.rom_path, why);
+            std::process::exit(1);
+        }
+    };
+    snes.abus.set_strict_ppu_access(args.strict_ppu_access);
+    let save_file = SaveFile::load(&config.rom_path, &mut snes);
     let debugger = Debugger::new();
 
     // TODO: Give mutable config, update window size for write out
-    let window = Window::new("Super Rustycom", &config, snes, debugger);
+    let window = Window::new("Super Rustycom", &config, snes, debugger, save_file);
     window.main_loop();
 
     config.save();
@@ -0,0 +1,70 @@
+use log::{error, info};
+use super_rustycom_core::snes::Snes;
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// How often changed SRAM is written to disk while running
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Battery backed cartridge RAM persisted in a .srm file next to the ROM
+pub struct SaveFile {
+    path: PathBuf,
+    /// SRAM as it was last written to disk
+    saved: Vec<u8>,
+    last_check: Instant,
+}
+
+impl SaveFile {
+    /// Loads the save file of `rom_path` into `snes` if it exists
+    ///
+    /// Returns `None` if the cartridge has no battery backed RAM.
+    pub fn load(rom_path: &str, snes: &mut Snes) -> Option<SaveFile> {
+        let battery = snes.rom_header().chipset.battery;
+        if !battery || snes.abus.sram().is_empty() {
+            return None;
+        }
+
+        let path = Path::new(rom_path).with_extension("srm");
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                snes.abus.load_sram(&bytes);
+                info!("Loaded SRAM from {}", path.display());
+            }
+            Err(why) if why.kind() == ErrorKind::NotFound => {}
+            Err(why) => error!("Could not read {}: {}", path.display(), why),
+        }
+
+        Some(SaveFile {
+            path,
+            saved: snes.abus.sram().to_vec(),
+            last_check: Instant::now(),
+        })
+    }
+
+    /// Saves SRAM if enough time has passed since the last check
+    pub fn update(&mut self, snes: &Snes) {
+        if self.last_check.elapsed() >= SAVE_INTERVAL {
+            self.last_check = Instant::now();
+            self.save(snes);
+        }
+    }
+
+    /// Writes SRAM to disk if it has changed since it was last written
+    pub fn save(&mut self, snes: &Snes) {
+        let sram = snes.abus.sram();
+        if sram == self.saved.as_slice() {
+            return;
+        }
+        match std::fs::write(&self.path, sram) {
+            Ok(()) => {
+                self.saved = sram.to_vec();
+                info!("Saved SRAM to {}", self.path.display());
+            }
+            Err(why) => error!("Could not write {}: {}", self.path.display(), why),
+        }
+    }
+}
@@ -16,9 +16,11 @@ pub struct Ui {
     execution: windows::Execution,
     wram: windows::Memory,
     apu_ram: windows::Memory,
+    apu_memory: windows::Memory,
     vram: windows::Memory,
     cpu: windows::Cpu,
     smp: windows::Smp,
+    screen: windows::Screen,
     palettes: windows::Palettes,
     oam: windows::SpriteAttributes,
 }
@@ -38,9 +40,17 @@ impl Ui {
             execution: windows::Execution::new(true),
             wram: windows::Memory::new("WRAM", false, MemoryMode::HexDump, context, textures),
             apu_ram: windows::Memory::new("APU RAM", false, MemoryMode::HexDump, context, textures),
+            apu_memory: windows::Memory::new(
+                "APU memory",
+                false,
+                MemoryMode::HexDump,
+                context,
+                textures,
+            ),
             vram: windows::Memory::new("VRAM", true, MemoryMode::Tiles, context, textures),
             cpu: windows::Cpu::new(true),
             smp: windows::Smp::new(false),
+            screen: windows::Screen::new(true, context, textures),
             palettes: windows::Palettes::new(true),
             oam: windows::SpriteAttributes::new(true),
         }
@@ -69,8 +79,15 @@ impl Ui {
         self.execution
             .draw(ui, snes, data, debugger, &mut full_reset_triggered);
         self.wram.draw(ui, snes.abus.wram(), snes.abus.cgram());
-        self.apu_ram.draw(ui, snes.apu.bus.ram(), snes.abus.cgram());
+        self.apu_ram
+            .draw(ui, snes.abus.apu().bus.ram(), snes.abus.cgram());
+        // RAM as the SMP sees it, only built when shown as it's a full copy
+        if self.apu_memory.opened {
+            let smp_view = snes.abus.apu().bus.smp_view();
+            self.apu_memory.draw(ui, &smp_view, snes.abus.cgram());
+        }
         self.vram.draw(ui, snes.abus.vram(), snes.abus.cgram());
+        self.screen.draw(ui, snes.abus.framebuffer());
         self.palettes.draw(ui, snes);
         self.oam.draw(ui, snes);
         self.cpu.draw(ui, snes, resolution);
@@ -104,8 +121,10 @@ impl Ui {
             ui.menu("APU", || {
                 toggle!(ui.menu_item("SMP registers"), self.smp.opened);
                 toggle!(ui.menu_item("APU RAM"), self.apu_ram.opened);
+                toggle!(ui.menu_item("APU memory"), self.apu_memory.opened);
             });
             ui.menu("PPU", || {
+                toggle!(ui.menu_item("Screen"), self.screen.opened);
                 toggle!(ui.menu_item("Palettes"), self.palettes.opened);
             });
             ui.menu("PPU", || {
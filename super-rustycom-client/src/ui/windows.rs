@@ -10,7 +10,13 @@ use imgui_glium_renderer::Texture;
 use itertools::Itertools;
 use std::{rc::Rc, str::FromStr, string::ToString};
 use strum::{Display, EnumString, EnumVariantNames, VariantNames};
-use super_rustycom_core::snes::Snes;
+use super_rustycom_core::{
+    ppu::{
+        Framebuffer, SCREEN_HEIGHT_INTERLACE, SCREEN_HEIGHT_OVERSCAN, SCREEN_WIDTH,
+        SCREEN_WIDTH_HIRES,
+    },
+    snes::Snes,
+};
 
 use crate::{
     debugger::{
@@ -38,6 +44,7 @@ const MEMORY_HEX_WINDOW_SIZE: [f32; 2] = [388.0, 344.0];
 const MEMORY_TILE_WINDOW_SIZE: [f32; 2] = [528.0, 382.0];
 const MEMORY_TILE_CHILD_WINDOW_SIZE: [f32; 2] = [527.0, 324.0];
 const MEMORY_TILE_WINDOW_TEXTURE_SCALE: f32 = 4.0;
+const SCREEN_WINDOW_TEXTURE_SCALE: f32 = 2.0;
 const ROWS_IN_TILE: u16 = 8;
 const COLUMNS_IN_TILE: u16 = 8;
 const PIXELS_IN_TILE: u16 = ROWS_IN_TILE * COLUMNS_IN_TILE;
@@ -128,7 +135,7 @@ impl Execution {
 
                     ui.same_line();
                     if ui.button("Cpu reset") {
-                        snes.cpu.reset(&mut snes.abus);
+                        snes.cpu.reset(&snes.abus);
                         data.clear_history();
                         debugger.state = DebugState::Active;
                     }
@@ -432,6 +439,96 @@ where
     changed
 }
 
+pub struct Screen {
+    pub opened: bool,
+    texture: Rc<Texture2d>,
+    texture_id: TextureId,
+}
+
+impl Screen {
+    pub fn new<F>(opened: bool, context: &F, textures: &mut Textures<Texture>) -> Self
+    where
+        F: ?Sized + Facade,
+    {
+        // Sized to fit the largest picture, smaller ones only use the top left corner
+        let texture = Rc::new(
+            Texture2d::empty(
+                context,
+                SCREEN_WIDTH_HIRES as u32,
+                SCREEN_HEIGHT_INTERLACE as u32,
+            )
+            .expect("Failed to create a screen texture"),
+        );
+        let sampler = SamplerBehavior {
+            magnify_filter: MagnifySamplerFilter::Nearest,
+            minify_filter: MinifySamplerFilter::Nearest,
+            ..SamplerBehavior::default()
+        };
+
+        let texture_id = textures.insert(Texture {
+            texture: Rc::clone(&texture),
+            sampler,
+        });
+
+        Self {
+            opened,
+            texture,
+            texture_id,
+        }
+    }
+
+    pub fn draw(&mut self, ui: &mut imgui::Ui, framebuffer: &Framebuffer) {
+        if self.opened {
+            let width = framebuffer.width() as u32;
+            let height = framebuffer.height() as u32;
+            let image = RawImage2d::from_raw_rgb(framebuffer.pixels().to_vec(), (width, height));
+            self.texture.write(
+                Rect {
+                    left: 0,
+                    bottom: 0,
+                    width,
+                    height,
+                },
+                image,
+            );
+
+            let texture = &self.texture;
+            let texture_id = self.texture_id;
+            ui.window("Screen")
+                .position(
+                    [
+                        EXECUTION_WINDOW_SIZE[0] + MEMORY_TILE_WINDOW_SIZE[0],
+                        MENU_BAR_HEIGHT,
+                    ],
+                    imgui::Condition::Appearing,
+                )
+                .always_auto_resize(true)
+                .collapsible(false)
+                .opened(&mut self.opened)
+                .build(|| {
+                    // Hi-res and interlaced pictures are shown at the same size as normal ones
+                    let shown_height = if (height as usize) > SCREEN_HEIGHT_OVERSCAN {
+                        height / 2
+                    } else {
+                        height
+                    };
+                    Image::new(
+                        texture_id,
+                        [
+                            (SCREEN_WIDTH as f32) * SCREEN_WINDOW_TEXTURE_SCALE,
+                            (shown_height as f32) * SCREEN_WINDOW_TEXTURE_SCALE,
+                        ],
+                    )
+                    .uv1([
+                        (width as f32) / (texture.width() as f32),
+                        (height as f32) / (texture.height() as f32),
+                    ])
+                    .build(ui);
+                });
+        }
+    }
+}
+
 pub struct Palettes {
     pub opened: bool,
 }
@@ -627,7 +724,7 @@ impl Smp {
                 .collapsible(false)
                 .opened(&mut self.opened)
                 .build(|| {
-                    for row in smp_status_str(&snes.apu.smp) {
+                    for row in smp_status_str(&snes.abus.apu().smp) {
                         ui.text(row);
                     }
                 });
@@ -18,6 +18,7 @@ use crate::{
     debugger::{disassemble_current, DebugState, Debugger},
     draw_data::DrawData,
     expect,
+    save_file::SaveFile,
     time_source::TimeSource,
     ui,
 };
@@ -33,10 +34,17 @@ pub struct Window {
 
     snes: Snes,
     debugger: Debugger,
+    save_file: Option<SaveFile>,
 }
 
 impl Window {
-    pub fn new(title: &str, config: &Config, snes: Snes, debugger: Debugger) -> Self {
+    pub fn new(
+        title: &str,
+        config: &Config,
+        snes: Snes,
+        debugger: Debugger,
+        save_file: Option<SaveFile>,
+    ) -> Self {
         // Create window and gl context
         let event_loop = EventLoop::new();
 
@@ -64,6 +72,7 @@ impl Window {
             ui,
             snes,
             debugger,
+            save_file,
         }
     }
 
@@ -74,6 +83,7 @@ impl Window {
             mut ui,
             mut snes,
             mut debugger,
+            mut save_file,
         } = self;
 
         let mut quit = false;
@@ -187,6 +197,10 @@ impl Window {
                 }
             }
 
+            if let Some(save_file) = &mut save_file {
+                save_file.update(&snes);
+            }
+
             // Need to do ui prepare, draw and render here instead of wrapping in methods.
             // Context::frame() requires &mut, so any calls/references to the wrapping struct
             // would be invalid while frame_ui is alive if it came from a member fn.
@@ -225,5 +239,9 @@ impl Window {
                 ui.ui.reset(display.get_context(), ui.renderer.textures());
             }
         }
+
+        if let Some(save_file) = &mut save_file {
+            save_file.save(&snes);
+        }
     }
 }
@@ -1,3 +1,4 @@
+use crate::apu::Apu;
 use crate::apu_io::ApuIo;
 use crate::cgram::Cgram;
 use crate::dma::Dma;
@@ -5,7 +6,9 @@ use crate::joypad::JoyIo;
 use crate::mmap;
 use crate::mpydiv::MpyDiv;
 use crate::oam::Oam;
-use crate::ppu_io::PpuIo;
+use crate::ppu::timing::{self, Timing};
+use crate::ppu::{Framebuffer, Ppu};
+use crate::ppu_io::{PpuIo, STAT77_RANGE_OVER, STAT77_TIME_OVER, STAT78_FIELD};
 use crate::rom::Rom;
 use crate::vram::Vram;
 
@@ -35,6 +38,22 @@ pub struct ABus {
     joy_io: JoyIo,
     /// Dma controller
     dma: Dma,
+    /// Beam position
+    timing: Timing,
+    /// Picture output
+    ppu: Ppu,
+    /// Audio processing unit, run lazily
+    apu: Apu,
+    /// Master clock ticks the APU hasn't been run for yet
+    apu_ticks: u32,
+    /// Master clock ticks the CPU has been stalled by DMA since last queried
+    stall_ticks: u32,
+    /// Master clock ticks spent in CPU cycles since last queried
+    cpu_ticks: u32,
+    /// Number of CPU cycles since last queried, both memory accesses and internal ones
+    cpu_cycles: u32,
+    /// `true` if VRAM, OAM and CGRAM writes are restricted to blanking like on hardware
+    strict_ppu_access: bool,
     /// Interrupt Enable and Joypad Request
     nmitimen: u8,
     /// H-count timer setting
@@ -45,7 +64,6 @@ pub struct ABus {
     memsel: u8,
     /// APU communication
     apu_io_r: ApuIo,
-    apu_io_w: ApuIo,
     /// Lower 8bit of WRAM address used by WMDATA reads
     wm_add_l: u8,
     /// Middle 8bit of WRAM address used by WMDATA reads
@@ -61,25 +79,32 @@ pub struct ABus {
 }
 
 impl ABus {
-    /// Initializes a new instance with default values and loads the given ROM
-    pub fn new(rom_bytes: Vec<u8>) -> ABus {
+    /// Initializes a new instance with default values and the given ROM
+    pub fn new(rom: Rom) -> ABus {
         // TODO: Randomize values?
         ABus {
             wram: Box::new([0; WRAM_SIZE]),
             vram: Vram::default(),
             oam: Oam::default(),
             cgram: Cgram::default(),
-            rom: Rom::new(rom_bytes),
+            rom,
             mpy_div: MpyDiv::new(),
             ppu_io: PpuIo::new(),
             joy_io: JoyIo::new(),
             dma: Dma::new(),
+            timing: Timing::default(),
+            ppu: Ppu::default(),
+            apu: Apu::default(),
+            apu_ticks: 0,
+            stall_ticks: 0,
+            cpu_ticks: 0,
+            cpu_cycles: 0,
+            strict_ppu_access: false,
             nmitimen: 0x00,
             htime: 0x01FF,
             vtime: 0x01FF,
             memsel: 0x00,
             apu_io_r: ApuIo::default(),
-            apu_io_w: ApuIo::default(),
             wm_add_l: 0x00,
             wm_add_m: 0x00,
             wm_add_h: 0x00,
@@ -102,12 +127,19 @@ impl ABus {
             ppu_io: PpuIo::new(),
             joy_io: JoyIo::new(),
             dma: Dma::new(),
+            timing: Timing::default(),
+            ppu: Ppu::default(),
+            apu: Apu::default(),
+            apu_ticks: 0,
+            stall_ticks: 0,
+            cpu_ticks: 0,
+            cpu_cycles: 0,
+            strict_ppu_access: false,
             nmitimen: 0x00,
             htime: 0x01FF,
             vtime: 0x01FF,
             memsel: 0x00,
             apu_io_r: ApuIo::default(),
-            apu_io_w: ApuIo::default(),
             wm_add_l: 0x00,
             wm_add_m: 0x00,
             wm_add_h: 0x00,
@@ -130,12 +162,396 @@ impl ABus {
         self.cgram.mem()
     }
 
-    pub fn apu_io(&self) -> ApuIo {
-        self.apu_io_w
+    /// Returns the last completed picture
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.ppu.framebuffer()
     }
 
-    pub fn write_smp_io(&mut self, io: ApuIo) {
-        self.apu_io_r = io;
+    /// Returns the current H and V counter values
+    pub fn beam_position(&self) -> (u16, u16) {
+        (self.timing.h(), self.timing.v())
+    }
+
+    /// Advances the hardware by `ticks` master clock ticks
+    ///
+    /// The PPU beam and the flags it drives are updated right away. The APU is only run when the
+    /// CPU accesses its ports or it has fallen too far behind.
+    pub fn advance(&mut self, ticks: u32) {
+        self.timing.add_ticks(ticks);
+        while self.timing.step_dot() {
+            self.update_beam_events();
+        }
+        self.apu_ticks += ticks;
+        if self.apu_ticks >= APU_SYNC_TICKS {
+            self.sync_apu();
+        }
+    }
+
+    /// Runs the APU up to the current master clock tick
+    fn sync_apu(&mut self) {
+        self.apu.run(self.apu_ticks);
+        self.apu_ticks = 0;
+        self.apu_io_r = self.apu.bus.apu_io();
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// Returns the audio generated since last call, interleaved left and right at 32 kHz
+    pub fn take_apu_samples(&mut self) -> Vec<i16> {
+        self.sync_apu();
+        self.apu.bus.take_samples()
+    }
+
+    fn update_beam_events(&mut self) {
+        let (h, v) = self.beam_position();
+        match h {
+            HDMA_INIT_H if v == 0 => self.init_hdma(),
+            HDMA_LINE_H if v < self.vblank_start() => self.hdma_line(),
+            RENDER_H if v > 0 && v < self.vblank_start() => self.ppu.render_line(
+                v,
+                &mut self.ppu_io,
+                self.vram.mem(),
+                &self.oam,
+                self.cgram.mem(),
+            ),
+            0 => {
+                if v == 0 {
+                    // Frame start
+                    self.ppu_io.stat_77 &= !(STAT77_RANGE_OVER | STAT77_TIME_OVER);
+                    self.ppu.start_frame();
+                    if self.ppu.field() {
+                        self.ppu_io.stat_78 |= STAT78_FIELD;
+                    } else {
+                        self.ppu_io.stat_78 &= !STAT78_FIELD;
+                    }
+                    self.hvb_joy &= !HVBJOY_VBLANK;
+                    self.rd_nmi &= !RDNMI_NMI;
+                } else if v == self.vblank_start() {
+                    self.ppu
+                        .end_frame((v - 1) as usize, self.ppu_io.setini & SETINI_INTERLACE != 0);
+                    if self.ppu_io.ini_disp & INIDISP_FORCED_BLANK == 0 {
+                        self.oam.reload_addr();
+                    }
+                    self.hvb_joy |= HVBJOY_VBLANK;
+                    self.rd_nmi |= RDNMI_NMI;
+                }
+            }
+            timing::HBLANK_END => self.hvb_joy &= !HVBJOY_HBLANK,
+            timing::HBLANK_START => self.hvb_joy |= HVBJOY_HBLANK,
+            _ => (),
+        }
+
+        let timer_triggered = match (self.nmitimen & NMITIMEN_TIMER) >> 4 {
+            0b00 => false,
+            0b01 => h == self.htime,
+            0b10 => h == 0 && v == self.vtime,
+            0b11 => h == self.htime && v == self.vtime,
+            _ => unreachable!(),
+        };
+        if timer_triggered {
+            self.time_up |= TIMEUP_IRQ;
+        }
+    }
+
+    /// Returns the cartridge
+    pub fn rom(&self) -> &Rom {
+        &self.rom
+    }
+
+    /// Returns the contents of cartridge SRAM, empty if the cartridge has none
+    pub fn sram(&self) -> &[u8] {
+        self.rom.sram()
+    }
+
+    /// Replaces the contents of cartridge SRAM, e.g. with a previously saved one
+    pub fn load_sram(&mut self, bytes: &[u8]) {
+        self.rom.load_sram(bytes);
+    }
+
+    /// Returns `true` if VRAM, OAM and CGRAM writes are restricted to blanking
+    pub fn strict_ppu_access(&self) -> bool {
+        self.strict_ppu_access
+    }
+
+    /// Sets whether VRAM, OAM and CGRAM writes are restricted to blanking like on hardware
+    ///
+    /// Writes outside blanking are dropped or misdirected and logged as warnings.
+    pub fn set_strict_ppu_access(&mut self, enabled: bool) {
+        self.strict_ppu_access = enabled;
+    }
+
+    /// Returns `true` if the PPU isn't using VRAM and OAM at the current beam position
+    fn oam_vram_accessible(&self) -> bool {
+        !self.strict_ppu_access
+            || self.ppu_io.ini_disp & INIDISP_FORCED_BLANK != 0
+            || self.timing.v() >= self.vblank_start()
+    }
+
+    /// Returns `true` if the PPU isn't using CGRAM at the current beam position
+    fn cgram_accessible(&self) -> bool {
+        // CGRAM is also free during H-blank
+        let h = self.timing.h();
+        self.oam_vram_accessible() || !(RENDER_H..timing::HBLANK_START).contains(&h)
+    }
+
+    /// Returns the OAM byte address the PPU is reading at the current beam position
+    ///
+    /// Sprites are evaluated two dots each from the first one, the evaluation is approximated to
+    /// span the whole line.
+    fn oam_fetch_addr(&self) -> usize {
+        let evaluated = (self.timing.h() as usize / 2).min(127);
+        ((self.oam.first_sprite() + evaluated) % 128) * 4
+    }
+
+    /// Returns the CGRAM color index the PPU is reading at the current beam position
+    ///
+    /// Only valid while CGRAM isn't accessible, the line has been rendered by then.
+    fn cgram_fetch_index(&self) -> u8 {
+        let x = self.timing.h().saturating_sub(RENDER_H) as usize;
+        self.ppu.cgram_fetch(x)
+    }
+
+    fn log_dropped_write(&self, mem: &str) {
+        let (h, v) = self.beam_position();
+        warn!("{} write outside blanking at H {} V {}", mem, h, v);
+    }
+
+    /// Returns the first line of V-blank based on the current overscan setting
+    fn vblank_start(&self) -> u16 {
+        if self.ppu_io.setini & SETINI_OVERSCAN != 0 {
+            timing::VBLANK_START_OVERSCAN
+        } else {
+            timing::VBLANK_START
+        }
+    }
+
+    /// Returns the master clock ticks the CPU has been stalled since the last call and resets the
+    /// count
+    ///
+    /// The PPU has already been advanced by these ticks.
+    pub fn take_stall_ticks(&mut self) -> u32 {
+        let ticks = self.stall_ticks;
+        self.stall_ticks = 0;
+        ticks
+    }
+
+    /// Returns the number of CPU cycles since the ticks were last queried
+    pub fn cpu_cycles(&self) -> u32 {
+        self.cpu_cycles
+    }
+
+    /// Returns the master clock ticks spent in CPU cycles since last queried
+    ///
+    /// The PPU has already been advanced by these ticks.
+    pub fn take_cpu_ticks(&mut self) -> u32 {
+        let ticks = self.cpu_ticks;
+        self.cpu_ticks = 0;
+        self.cpu_cycles = 0;
+        ticks
+    }
+
+    /// Spends a CPU cycle without a memory access
+    pub fn cpu_idle(&mut self) {
+        self.cpu_cycle(FAST_ACCESS_TICKS);
+    }
+
+    /// Returns the master clock ticks a CPU access to `addr` takes
+    pub fn access_speed(&self, addr: u32) -> u32 {
+        let bank = (addr >> 16) as usize;
+        let bank_addr = (addr & 0x00FFFF) as usize;
+        match bank {
+            mmap::WS1_SYSLR_FIRST_BANK..=mmap::WS1_SYSLR_LAST_BANK
+            | mmap::WS2_SYSLR_FIRST_BANK..=mmap::WS2_SYSLR_LAST_BANK => match bank_addr {
+                mmap::WRAM_MIRR_FIRST..=mmap::WRAM_MIRR_LAST => SLOW_ACCESS_TICKS,
+                mmap::OLD_JOY_FIRST..=mmap::OLD_JOY_LAST => XSLOW_ACCESS_TICKS,
+                mmap::EXP_FIRST..=mmap::EXP_LAST => SLOW_ACCESS_TICKS,
+                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom_speed(bank),
+                _ => FAST_ACCESS_TICKS,
+            },
+            mmap::WS1_HIROM_FIRST_BANK..=mmap::WRAM_LAST_BANK => SLOW_ACCESS_TICKS,
+            mmap::WS2_HIROM_FIRST_BANK..=mmap::WS2_HIROM_LAST_BANK => self.rom_speed(bank),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the master clock ticks a ROM access in `bank` takes
+    ///
+    /// Only WS2 can be set to FastROM speed.
+    fn rom_speed(&self, bank: usize) -> u32 {
+        if bank >= mmap::WS2_SYSLR_FIRST_BANK && self.memsel & MEMSEL_FASTROM != 0 {
+            FAST_ACCESS_TICKS
+        } else {
+            SLOW_ACCESS_TICKS
+        }
+    }
+
+    /// Advances the rest of the hardware by a CPU cycle of `ticks` master clock ticks
+    ///
+    /// This is done before the access itself so that it sees the beam where the cycle ends.
+    fn cpu_cycle(&mut self, ticks: u32) {
+        self.cpu_ticks += ticks;
+        self.cpu_cycles += 1;
+        self.advance(ticks);
+    }
+
+    /// Stalls the CPU for `ticks` master clock ticks while the rest of the hardware advances
+    fn stall(&mut self, ticks: u32) {
+        self.stall_ticks += ticks;
+        self.advance(ticks);
+    }
+
+    /// Runs GP-DMA on all channels enabled in MDMAEN in priority order
+    fn gp_dma(&mut self) {
+        self.stall(DMA_SYNC_TICKS);
+        for channel in 0..8 {
+            if self.dma.read_mdma_en() & (1 << channel) == 0 {
+                continue;
+            }
+            self.stall(DMA_CHANNEL_TICKS);
+
+            let mut index = 0;
+            loop {
+                let a_addr = self.dma.a_addr(channel);
+                let b_addr = self.dma.b_addr(channel, index);
+                self.dma_transfer(channel, a_addr, b_addr);
+                self.stall(DMA_BYTE_TICKS);
+                index += 1;
+
+                if self.dma.advance_gp(channel) {
+                    break;
+                }
+                if self.dma.read_mdma_en() & (1 << channel) == 0 {
+                    // HDMA on the same channel cut the transfer short
+                    break;
+                }
+            }
+            self.dma.finish_gp(channel);
+        }
+    }
+
+    /// Stalls the CPU for `ticks` master clock ticks during HDMA
+    ///
+    /// HDMA runs while the beam is being advanced so the ticks are left for the ongoing update to
+    /// consume.
+    fn hdma_stall(&mut self, ticks: u32) {
+        self.stall_ticks += ticks;
+        self.timing.add_ticks(ticks);
+        self.apu_ticks += ticks;
+    }
+
+    /// Starts HDMA on all enabled channels at the beginning of a frame
+    fn init_hdma(&mut self) {
+        let enabled = self.dma.read_hdma_en();
+        if enabled == 0 {
+            return;
+        }
+        self.hdma_stall(DMA_SYNC_TICKS);
+        for channel in 0..8 {
+            if enabled & (1 << channel) == 0 {
+                continue;
+            }
+            self.hdma_stall(DMA_CHANNEL_TICKS);
+            self.dma.start_hdma(channel);
+            self.load_hdma_entry(channel);
+        }
+    }
+
+    /// Loads the next line counter and indirect address from the HDMA table of `channel`
+    fn load_hdma_entry(&mut self, channel: usize) {
+        let line_counter = self.dma_read_a(self.dma.hdma_table_addr(channel));
+        self.dma.advance_hdma_table(channel);
+        self.dma.set_hdma_line_counter(channel, line_counter);
+
+        if self.dma.hdma_indirect(channel) {
+            let low = self.dma_read_a(self.dma.hdma_table_addr(channel));
+            self.dma.advance_hdma_table(channel);
+            let high = self.dma_read_a(self.dma.hdma_table_addr(channel));
+            self.dma.advance_hdma_table(channel);
+            self.dma.set_hdma_indirect_addr(channel, low, high);
+            self.hdma_stall(2 * DMA_BYTE_TICKS);
+        }
+    }
+
+    /// Runs the per-line HDMA transfers on all active channels
+    fn hdma_line(&mut self) {
+        let active = self.dma.hdma_active();
+        if active == 0 {
+            return;
+        }
+        self.hdma_stall(DMA_SYNC_TICKS);
+        for channel in 0..8 {
+            if active & (1 << channel) == 0 {
+                continue;
+            }
+            // HDMA takes priority and terminates GP-DMA on the same channel
+            self.dma.finish_gp(channel);
+            self.hdma_stall(DMA_CHANNEL_TICKS);
+
+            if self.dma.hdma_do_transfer(channel) {
+                for index in 0..self.dma.unit_len(channel) {
+                    let a_addr = self.dma.hdma_data_addr(channel);
+                    let b_addr = self.dma.b_addr(channel, index);
+                    self.dma_transfer(channel, a_addr, b_addr);
+                    self.dma.advance_hdma_data(channel);
+                    self.hdma_stall(DMA_BYTE_TICKS);
+                }
+            }
+
+            if self.dma.step_hdma_line_counter(channel) {
+                self.load_hdma_entry(channel);
+            }
+        }
+    }
+
+    /// Transfers a single byte between `a_addr` and `b_addr` in the direction set for `channel`
+    fn dma_transfer(&mut self, channel: usize, a_addr: u32, b_addr: usize) {
+        if self.dma.b_to_a(channel) {
+            let value = self.cpu_read_sys(b_addr);
+            self.dma_write_a(a_addr, value);
+        } else {
+            let value = self.dma_read_a(a_addr);
+            self.cpu_write_sys(b_addr, value);
+        }
+    }
+
+    /// Reads A-bus `addr` as DMA sees it
+    ///
+    /// DMA can't access the B-bus or the CPU I/O registers through A-bus.
+    fn dma_read_a(&mut self, addr: u32) -> u8 {
+        if dma_a_bus_blocked(addr) {
+            warn!("DMA read from blocked A-bus address ${:06X}", addr);
+            0
+        } else {
+            self.read8(addr)
+        }
+    }
+
+    /// Writes `value` to A-bus `addr` as DMA sees it
+    fn dma_write_a(&mut self, addr: u32, value: u8) {
+        if dma_a_bus_blocked(addr) {
+            warn!("DMA write to blocked A-bus address ${:06X}", addr);
+        } else {
+            self.write8(addr, value);
+        }
+    }
+
+    /// Returns `true` if the NMI output is asserted, i.e. the V-blank NMI flag is set and the NMI
+    /// is enabled in NMITIMEN
+    pub fn nmi(&self) -> bool {
+        self.rd_nmi & RDNMI_NMI != 0 && self.nmitimen & NMITIMEN_NMI != 0
+    }
+
+    /// Returns `true` if the IRQ output is asserted by the H/V-timer
+    pub fn irq(&self) -> bool {
+        self.time_up & TIMEUP_IRQ != 0
+    }
+
+    /// Latches the current beam position to OPHCT and OPVCT
+    fn latch_counters(&mut self) {
+        let (h, v) = self.beam_position();
+        self.ppu_io.latch_counters(h, v);
     }
 
     fn cpu_read_sys(&mut self, addr: usize) -> u8 {
@@ -152,12 +568,17 @@ impl ABus {
                     mmap::RDOAM => self.oam.read_data(),
                     mmap::RDVRAML => self.vram.read_low(),
                     mmap::RDVRAMH => self.vram.read_high(),
+                    mmap::SLHV => {
+                        self.latch_counters();
+                        self.ppu_io.read(addr)
+                    }
                     _ => self.ppu_io.read(addr),
                 }
             }
             mmap::APU_IO_FIRST..=mmap::APU_IO_LAST => {
                 // APU IO
                 let port = (if addr < 0x2144 { addr } else { addr - 4 } as u8) & 0x0F;
+                self.sync_apu();
                 self.apu_io_r.read(port)
             }
             mmap::WMDATA => {
@@ -188,7 +609,7 @@ impl ABus {
             }
             mmap::TIMEUP => {
                 let val = self.time_up;
-                self.time_up &= 0b0111_1111;
+                self.time_up &= !TIMEUP_IRQ;
                 val
             }
             mmap::HVBJOY => self.hvb_joy,
@@ -289,27 +710,39 @@ impl ABus {
     }
 
     pub fn cpu_read8(&mut self, addr: u32) -> u8 {
+        self.cpu_cycle(self.access_speed(addr));
+        self.read8(addr)
+    }
+
+    /// Reads `addr` without counting it as a CPU access
+    fn read8(&mut self, addr: u32) -> u8 {
         let bank = (addr >> 16) as usize;
         let bank_addr = (addr & 0x00FFFF) as usize;
         match bank {
             mmap::WS1_SYSLR_FIRST_BANK..=mmap::WS1_SYSLR_LAST_BANK => match bank_addr {
+                mmap::EXP_FIRST..=mmap::EXP_LAST if self.rom.maps_sram(bank, bank_addr) => {
+                    self.rom.read8(bank, bank_addr)
+                }
                 mmap::SYS_FIRST..=mmap::SYS_LAST => self.cpu_read_sys(bank_addr),
-                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom.read_ws1_lo_rom8(bank, bank_addr),
+                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom.read8(bank, bank_addr),
                 _ => unreachable!(),
             },
             mmap::WS1_HIROM_FIRST_BANK..=mmap::WS1_HIROM_LAST_BANK => {
-                self.rom.read_ws1_hi_rom8(bank, bank_addr)
+                self.rom.read8(bank, bank_addr)
             }
             mmap::WRAM_FIRST_BANK..=mmap::WRAM_LAST_BANK => {
                 self.wram[(bank - mmap::WRAM_FIRST_BANK) * 0x10000 + bank_addr]
             }
             mmap::WS2_SYSLR_FIRST_BANK..=mmap::WS2_SYSLR_LAST_BANK => match bank_addr {
+                mmap::EXP_FIRST..=mmap::EXP_LAST if self.rom.maps_sram(bank, bank_addr) => {
+                    self.rom.read8(bank, bank_addr)
+                }
                 mmap::SYS_FIRST..=mmap::SYS_LAST => self.cpu_read_sys(bank_addr),
-                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom.read_ws2_lo_rom8(bank, bank_addr),
+                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom.read8(bank, bank_addr),
                 _ => unreachable!(),
             },
             mmap::WS2_HIROM_FIRST_BANK..=mmap::WS2_HIROM_LAST_BANK => {
-                self.rom.read_ws2_hi_rom8(bank, bank_addr)
+                self.rom.read8(bank, bank_addr)
             }
             _ => unreachable!(),
         }
@@ -319,23 +752,29 @@ impl ABus {
         let bank_addr = (addr & 0x00FFFF) as usize;
         match bank {
             mmap::WS1_SYSLR_FIRST_BANK..=mmap::WS1_SYSLR_LAST_BANK => match bank_addr {
+                mmap::EXP_FIRST..=mmap::EXP_LAST if self.rom.maps_sram(bank, bank_addr) => {
+                    self.rom.read8(bank, bank_addr)
+                }
                 mmap::SYS_FIRST..=mmap::SYS_LAST => self.cpu_peek_sys(bank_addr),
-                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom.read_ws1_lo_rom8(bank, bank_addr),
+                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom.read8(bank, bank_addr),
                 _ => unreachable!(),
             },
             mmap::WS1_HIROM_FIRST_BANK..=mmap::WS1_HIROM_LAST_BANK => {
-                self.rom.read_ws1_hi_rom8(bank, bank_addr)
+                self.rom.read8(bank, bank_addr)
             }
             mmap::WRAM_FIRST_BANK..=mmap::WRAM_LAST_BANK => {
                 self.wram[(bank - mmap::WRAM_FIRST_BANK) * 0x10000 + bank_addr]
             }
             mmap::WS2_SYSLR_FIRST_BANK..=mmap::WS2_SYSLR_LAST_BANK => match bank_addr {
+                mmap::EXP_FIRST..=mmap::EXP_LAST if self.rom.maps_sram(bank, bank_addr) => {
+                    self.rom.read8(bank, bank_addr)
+                }
                 mmap::SYS_FIRST..=mmap::SYS_LAST => self.cpu_peek_sys(bank_addr),
-                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom.read_ws2_lo_rom8(bank, bank_addr),
+                mmap::LOROM_FIRST..=mmap::LOROM_LAST => self.rom.read8(bank, bank_addr),
                 _ => unreachable!(),
             },
             mmap::WS2_HIROM_FIRST_BANK..=mmap::WS2_HIROM_LAST_BANK => {
-                self.rom.read_ws2_hi_rom8(bank, bank_addr)
+                self.rom.read8(bank, bank_addr)
             }
             _ => unreachable!(),
         }
@@ -426,15 +865,45 @@ impl ABus {
                 // PPU IO
                 match addr {
                     mmap::CGADD => self.cgram.write_addr(value),
-                    mmap::CGDATA => self.cgram.write_data(value),
+                    mmap::CGDATA => {
+                        if self.cgram_accessible() {
+                            self.cgram.write_data(value)
+                        } else {
+                            self.log_dropped_write("CGRAM");
+                            let index = self.cgram_fetch_index();
+                            self.cgram.write_data_at(index, value)
+                        }
+                    }
                     mmap::OAMADDL => self.oam.write_oamddl(value),
                     mmap::OAMADDH => self.oam.write_oamddh(value),
-                    mmap::OAMDATA => self.oam.write_data(value),
+                    mmap::OAMDATA => {
+                        if self.oam_vram_accessible() {
+                            self.oam.write_data(value)
+                        } else {
+                            self.log_dropped_write("OAM");
+                            let addr = self.oam_fetch_addr();
+                            self.oam.write_data_at(addr, value)
+                        }
+                    }
                     mmap::VMAIN => self.vram.write_vmain(value),
                     mmap::VMADDL => self.vram.write_vmaddl(value),
                     mmap::VMADDH => self.vram.write_vmaddh(value),
-                    mmap::VMDATAL => self.vram.write_vmdatal(value),
-                    mmap::VMDATAH => self.vram.write_vmdatah(value),
+                    mmap::VMDATAL => {
+                        if self.oam_vram_accessible() {
+                            self.vram.write_vmdatal(value)
+                        } else {
+                            self.log_dropped_write("VRAM");
+                            self.vram.skip_vmdatal()
+                        }
+                    }
+                    mmap::VMDATAH => {
+                        if self.oam_vram_accessible() {
+                            self.vram.write_vmdatah(value)
+                        } else {
+                            self.log_dropped_write("VRAM");
+                            self.vram.skip_vmdatah()
+                        }
+                    }
                     mmap::MPYL..=mmap::STAT78 => {
                         error!("Write ${:06X}: PPU IO read-only for cpu", addr)
                     }
@@ -444,7 +913,9 @@ impl ABus {
             mmap::APU_IO_FIRST..=mmap::APU_IO_LAST => {
                 // APU IO
                 let port = (if addr < 0x2144 { addr } else { addr - 4 } as u8) & 0x0F;
-                self.apu_io_w.write(port, value);
+                // APU has to see the earlier values up to this point
+                self.sync_apu();
+                self.apu.bus.write_cpu_io(port, value);
             }
             mmap::WMDATA => {
                 let wram_addr = ((self.wm_add_h as usize) << 16)
@@ -456,18 +927,33 @@ impl ABus {
             mmap::WMADDM => self.wm_add_m = value,
             mmap::WMADDH => self.wm_add_h = value,
             mmap::JOYWR => self.joy_io.set_joy_wr(value),
-            mmap::NMITIMEN => self.nmitimen = value,
-            mmap::WRIO => self.joy_io.set_wr_io(value),
+            mmap::NMITIMEN => {
+                self.nmitimen = value;
+                // Disabling the timer also acknowledges a pending IRQ
+                if value & NMITIMEN_TIMER == 0 {
+                    self.time_up &= !TIMEUP_IRQ;
+                }
+            }
+            mmap::WRIO => {
+                // High to low transition of bit 7 latches the counters like reading SLHV
+                if self.joy_io.wr_io() & 0x80 != 0 && value & 0x80 == 0 {
+                    self.latch_counters();
+                }
+                self.joy_io.set_wr_io(value)
+            }
             mmap::WRMPYA => self.mpy_div.set_multiplicand(value),
             mmap::WRMPYB => self.mpy_div.set_multiplier_and_start_multiply(value),
             mmap::WRDIVL => self.mpy_div.set_dividend_low(value),
             mmap::WRDIVH => self.mpy_div.set_dividend_high(value),
             mmap::WRDIVB => self.mpy_div.set_divisor_and_start_division(value),
             mmap::HTIMEL => self.htime = (self.htime & 0xFF00) | value as u16,
-            mmap::HTIMEH => self.htime = ((value as u16) << 8) | (self.htime & 0x00FF),
+            mmap::HTIMEH => self.htime = (((value & 0x01) as u16) << 8) | (self.htime & 0x00FF),
             mmap::VTIMEL => self.vtime = (self.vtime & 0xFF00) | value as u16,
-            mmap::VTIMEH => self.vtime = ((value as u16) << 8) | (self.vtime & 0x00FF),
-            mmap::MDMAEN => self.dma.write_mdma_en(value),
+            mmap::VTIMEH => self.vtime = (((value & 0x01) as u16) << 8) | (self.vtime & 0x00FF),
+            mmap::MDMAEN => {
+                self.dma.write_mdma_en(value);
+                self.gp_dma();
+            }
             mmap::HDMAEN => self.dma.write_hdma_en(value),
             mmap::MEMSEL => self.memsel = value,
             mmap::DMA_FIRST..=mmap::DMA_LAST => {
@@ -486,31 +972,43 @@ impl ABus {
     }
 
     pub fn cpu_write8(&mut self, addr: u32, value: u8) {
+        self.cpu_cycle(self.access_speed(addr));
+        self.write8(addr, value);
+    }
+
+    /// Writes `addr` without counting it as a CPU access
+    fn write8(&mut self, addr: u32, value: u8) {
         let bank = (addr >> 16) as usize;
         let bank_addr = (addr & 0x00FFFF) as usize;
         match bank {
             mmap::WS1_SYSLR_FIRST_BANK..=mmap::WS1_SYSLR_LAST_BANK => match bank_addr {
+                mmap::EXP_FIRST..=mmap::EXP_LAST if self.rom.maps_sram(bank, bank_addr) => {
+                    self.rom.write8(bank, bank_addr, value);
+                }
                 mmap::SYS_FIRST..=mmap::SYS_LAST => self.cpu_write_sys(bank_addr, value),
                 mmap::LOROM_FIRST..=mmap::LOROM_LAST => {
-                    self.rom.write_ws1_lo_rom8(bank, bank_addr, value);
+                    self.rom.write8(bank, bank_addr, value);
                 }
                 _ => unreachable!(),
             },
             mmap::WS1_HIROM_FIRST_BANK..=mmap::WS1_HIROM_LAST_BANK => {
-                self.rom.write_ws1_hi_rom8(bank, bank_addr, value);
+                self.rom.write8(bank, bank_addr, value);
             }
             mmap::WRAM_FIRST_BANK..=mmap::WRAM_LAST_BANK => {
                 self.wram[(bank - mmap::WRAM_FIRST_BANK) * 0x10000 + bank_addr] = value;
             }
             mmap::WS2_SYSLR_FIRST_BANK..=mmap::WS2_SYSLR_LAST_BANK => match bank_addr {
+                mmap::EXP_FIRST..=mmap::EXP_LAST if self.rom.maps_sram(bank, bank_addr) => {
+                    self.rom.write8(bank, bank_addr, value);
+                }
                 mmap::SYS_FIRST..=mmap::SYS_LAST => self.cpu_write_sys(bank_addr, value),
                 mmap::LOROM_FIRST..=mmap::LOROM_LAST => {
-                    self.rom.write_ws2_lo_rom8(bank, bank_addr, value);
+                    self.rom.write8(bank, bank_addr, value);
                 }
                 _ => unreachable!(),
             },
             mmap::WS2_HIROM_FIRST_BANK..=mmap::WS2_HIROM_LAST_BANK => {
-                self.rom.write_ws2_hi_rom8(bank, bank_addr, value);
+                self.rom.write8(bank, bank_addr, value);
             }
             _ => unreachable!(),
         }
@@ -550,6 +1048,70 @@ impl ABus {
         self.cpu_write8(page_wrapping_add(addr, 2), (value >> 16) as u8);
     }
 }
+/// V-blank NMI flag in RDNMI
+const RDNMI_NMI: u8 = 0b1000_0000;
+/// V-blank NMI enable in NMITIMEN
+const NMITIMEN_NMI: u8 = 0b1000_0000;
+/// H/V-timer IRQ mode in NMITIMEN
+const NMITIMEN_TIMER: u8 = 0b0011_0000;
+/// H/V-timer IRQ flag in TIMEUP
+const TIMEUP_IRQ: u8 = 0b1000_0000;
+/// V-blank flag in HVBJOY
+const HVBJOY_VBLANK: u8 = 0b1000_0000;
+/// H-blank flag in HVBJOY
+const HVBJOY_HBLANK: u8 = 0b0100_0000;
+/// Forced blank bit in INIDISP
+const INIDISP_FORCED_BLANK: u8 = 0b1000_0000;
+/// FastROM bit in MEMSEL
+const MEMSEL_FASTROM: u8 = 0b0000_0001;
+/// Overscan bit in SETINI
+const SETINI_OVERSCAN: u8 = 0b0000_0100;
+/// Interlace bit in SETINI
+const SETINI_INTERLACE: u8 = 0b0000_0001;
+
+// DMA timing
+/// Master clock ticks spent syncing the CPU and DMA clocks around a transfer, actually varies from
+/// 12 to 24 depending on the CPU clock alignment
+const DMA_SYNC_TICKS: u32 = 18;
+/// Master clock ticks of overhead per DMA channel
+const DMA_CHANNEL_TICKS: u32 = 8;
+/// Master clock ticks per byte transferred by DMA
+const DMA_BYTE_TICKS: u32 = 8;
+
+// Dots of per-line events
+/// Dot where HDMA is initialized on the first line of a frame
+const HDMA_INIT_H: u16 = 6;
+/// Dot where HDMA transfers are done on each visible line
+const HDMA_LINE_H: u16 = 278;
+/// Dot where the current line is rendered, after the PPU has fetched its first tiles
+const RENDER_H: u16 = 22;
+
+// CPU and APU timing
+/// Master clock ticks of a CPU cycle accessing FastROM, I/O or nothing at all
+const FAST_ACCESS_TICKS: u32 = 6;
+/// Master clock ticks of a CPU cycle accessing WRAM, SlowROM or the expansion area
+const SLOW_ACCESS_TICKS: u32 = 8;
+/// Master clock ticks of a CPU cycle accessing the old style joypad registers
+const XSLOW_ACCESS_TICKS: u32 = 12;
+/// Master clock ticks the APU is allowed to fall behind, a scanline
+const APU_SYNC_TICKS: u32 = 1364;
+
+/// Returns `true` if DMA can't access `addr` through A-bus
+fn dma_a_bus_blocked(addr: u32) -> bool {
+    let bank = (addr >> 16) as usize;
+    let bank_addr = (addr & 0x00FFFF) as usize;
+    let sys_bank = matches!(
+        bank,
+        mmap::WS1_SYSLR_FIRST_BANK..=mmap::WS1_SYSLR_LAST_BANK
+            | mmap::WS2_SYSLR_FIRST_BANK..=mmap::WS2_SYSLR_LAST_BANK
+    );
+    sys_bank
+        && matches!(
+            bank_addr,
+            mmap::BBUS_FIRST..=mmap::BBUS_LAST | mmap::NMITIMEN..=mmap::DMA_LAST
+        )
+}
+
 pub fn addr_wrapping_add(addr: u32, offset: u32) -> u32 {
     (addr + offset) & 0x00FFFFFF
 }
@@ -571,3 +1133,184 @@ pub fn bank_wrapping_sub(addr: u32, offset: u16) -> u32 {
 pub fn page_wrapping_sub(addr: u32, offset: u8) -> u32 {
     (addr & 0xFFFF00) | ((addr as u8).wrapping_sub(offset) as u32)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Advances `abus` until the beam reaches dot `h` of line `v`
+    fn run_to(abus: &mut ABus, h: u16, v: u16) {
+        while abus.beam_position() != (h, v) {
+            abus.advance(1);
+        }
+    }
+
+    /// Advances `abus` until the H/V-timer IRQ is asserted and returns the beam position
+    ///
+    /// The IRQ is acknowledged through TIMEUP before returning.
+    fn next_irq(abus: &mut ABus) -> (u16, u16) {
+        while !abus.irq() {
+            abus.advance(1);
+        }
+        let position = abus.beam_position();
+        assert_eq!(abus.cpu_read8(0x004211) & TIMEUP_IRQ, TIMEUP_IRQ);
+        position
+    }
+
+    /// Enables the H/V-timer IRQ in `mode` of NMITIMEN with `htime` and `vtime`
+    fn set_timer(abus: &mut ABus, mode: u8, htime: u16, vtime: u16) {
+        abus.cpu_write8(0x004207, htime as u8);
+        abus.cpu_write8(0x004208, (htime >> 8) as u8);
+        abus.cpu_write8(0x004209, vtime as u8);
+        abus.cpu_write8(0x00420A, (vtime >> 8) as u8);
+        abus.cpu_write8(0x004200, mode);
+    }
+
+    #[test]
+    fn h_irq() {
+        let mut abus = ABus::new_empty_rom();
+        set_timer(&mut abus, 0x10, 100, 50);
+        let (h, v) = next_irq(&mut abus);
+        assert_eq!(h, 100);
+        assert_eq!(next_irq(&mut abus), (100, v + 1));
+    }
+
+    #[test]
+    fn v_irq() {
+        let mut abus = ABus::new_empty_rom();
+        set_timer(&mut abus, 0x20, 100, 50);
+        assert_eq!(next_irq(&mut abus), (0, 50));
+        abus.advance(4);
+        assert_eq!(next_irq(&mut abus), (0, 50));
+    }
+
+    #[test]
+    fn hv_irq() {
+        let mut abus = ABus::new_empty_rom();
+        set_timer(&mut abus, 0x30, 100, 50);
+        assert_eq!(next_irq(&mut abus), (100, 50));
+        abus.advance(4);
+        assert_eq!(next_irq(&mut abus), (100, 50));
+    }
+
+    #[test]
+    fn timeup_clears_on_read() {
+        let mut abus = ABus::new_empty_rom();
+        set_timer(&mut abus, 0x20, 0, 10);
+        run_to(&mut abus, 1, 10);
+        assert!(abus.irq());
+        assert_eq!(abus.cpu_read8(0x004211) & TIMEUP_IRQ, TIMEUP_IRQ);
+        assert_eq!(abus.cpu_read8(0x004211) & TIMEUP_IRQ, 0);
+        assert!(!abus.irq());
+    }
+
+    #[test]
+    fn rdnmi_clears_on_read() {
+        let mut abus = ABus::new_empty_rom();
+        abus.cpu_write8(0x004200, NMITIMEN_NMI);
+        run_to(&mut abus, 1, timing::VBLANK_START);
+        assert!(abus.nmi());
+        assert_eq!(abus.cpu_read8(0x004210) & RDNMI_NMI, RDNMI_NMI);
+        assert_eq!(abus.cpu_read8(0x004210) & RDNMI_NMI, 0);
+        assert!(!abus.nmi());
+    }
+
+    /// Sets up channel 0 to HDMA from the table at $7E1000 to VMDATAL, which stores the bytes
+    /// in the low bytes of VRAM words from 0 on
+    ///
+    /// The beam is left in V-blank so that HDMA starts with the next frame.
+    fn hdma_to_vram(dma_p: u8, table: &[u8], data: &[u8]) -> ABus {
+        let mut abus = ABus::new_empty_rom();
+        run_to(&mut abus, 0, 230);
+        for (i, &value) in table.iter().enumerate() {
+            abus.wram[0x1000 + i] = value;
+        }
+        for (i, &value) in data.iter().enumerate() {
+            abus.wram[0x3000 + i] = value;
+        }
+        for &(addr, value) in &[
+            // VMAIN, VMADD
+            (0x002115, 0x00),
+            (0x002116, 0x00),
+            (0x002117, 0x00),
+            // DMAP0, BBAD0, A1T0, DASB0
+            (0x004300, dma_p),
+            (0x004301, 0x18),
+            (0x004302, 0x00),
+            (0x004303, 0x10),
+            (0x004304, 0x7E),
+            (0x004307, 0x7E),
+            // HDMAEN
+            (0x00420C, 0x01),
+        ] {
+            abus.cpu_write8(addr, value);
+        }
+        abus
+    }
+
+    /// Returns the low bytes of `count` VRAM words from word `first` on
+    fn vram_low_bytes(abus: &ABus, first: usize, count: usize) -> Vec<u8> {
+        (first..first + count).map(|w| abus.vram()[w * 2]).collect()
+    }
+
+    #[test]
+    fn hdma_direct_table() {
+        // Single transfer for two lines, then a transfer on each of two lines
+        let table = [0x02, 0xAA, 0x82, 0xBB, 0xCC, 0x00, 0x01, 0xDD];
+        let mut abus = hdma_to_vram(0x00, &table, &[]);
+        run_to(&mut abus, 0, 20);
+        assert_eq!(vram_low_bytes(&abus, 0, 4), [0xAA, 0xBB, 0xCC, 0x00]);
+        // Zero entry ends HDMA on the channel for the frame
+        assert_eq!(abus.dma.hdma_active(), 0x00);
+
+        // Table starts over on the next frame
+        run_to(&mut abus, 0, 230);
+        run_to(&mut abus, 0, 20);
+        assert_eq!(vram_low_bytes(&abus, 3, 4), [0xAA, 0xBB, 0xCC, 0x00]);
+    }
+
+    #[test]
+    fn hdma_indirect_table() {
+        // Pattern 2 writes VMDATAL twice per line, from $7E3000 on every line for two lines
+        let table = [0x82, 0x00, 0x30, 0x00];
+        let mut abus = hdma_to_vram(0x42, &table, &[0x11, 0x22, 0x33, 0x44, 0x55]);
+        run_to(&mut abus, 0, 20);
+        assert_eq!(vram_low_bytes(&abus, 0, 5), [0x11, 0x22, 0x33, 0x44, 0x00]);
+        assert_eq!(abus.dma.hdma_active(), 0x00);
+    }
+
+    #[test]
+    fn hdma_ends_gp_dma_on_same_channel() {
+        let mut abus = hdma_to_vram(0x00, &[0x7F, 0xAA, 0x00], &[]);
+        run_to(&mut abus, 0, 50);
+        // 64K bytes take many lines, HDMA on the next line stops the transfer
+        abus.cpu_write8(0x004305, 0x00);
+        abus.cpu_write8(0x004306, 0x00);
+        abus.cpu_write8(0x00420B, 0x01);
+        assert_eq!(abus.dma.read_mdma_en(), 0x00);
+        let remaining = abus.cpu_peek8(0x004305) as u16 | ((abus.cpu_peek8(0x004306) as u16) << 8);
+        assert!(remaining > 0xF000, "{:04X} bytes remaining", remaining);
+        assert_eq!(abus.beam_position().1, 50);
+    }
+
+    #[test]
+    fn cgram_write_during_active_display() {
+        let mut abus = ABus::new_empty_rom();
+        abus.set_strict_ppu_access(true);
+        // Display on with no layers, every pixel is the backdrop
+        abus.cpu_write8(0x002100, 0x0F);
+        abus.cpu_write8(0x002121, 0x10);
+        run_to(&mut abus, 100, 10);
+        abus.cpu_write8(0x002122, 0x34);
+        abus.cpu_write8(0x002122, 0x12);
+        // Bytes land in the backdrop color the PPU is fetching
+        assert_eq!(&abus.cgram()[0x00..0x02], &[0x34, 0x12]);
+        assert_eq!(&abus.cgram()[0x20..0x22], &[0x00, 0x00]);
+
+        // Address was still incremented and H-blank writes go there
+        run_to(&mut abus, timing::HBLANK_START + 2, 10);
+        abus.cpu_write8(0x002122, 0x78);
+        abus.cpu_write8(0x002122, 0x56);
+        assert_eq!(&abus.cgram()[0x22..0x24], &[0x78, 0x56]);
+    }
+}
@@ -1,12 +1,48 @@
+use super::dsp::Dsp;
 use crate::apu_io::ApuIo;
 
-/// 64 kB of RAM including the IPL ROM at $FFC0-FFFF and mirrored I/O-ports at $00F0-$00FF
+/// 64 kB of RAM including mirrored I/O-ports at $00F0-$00FF
 const RAM_SIZE: usize = 64 * 1024;
 
 pub struct Bus {
     ram: Box<[u8]>,
     /// Store the values written by the cpu, ours are in the corresponding RAM addresses
     cpu_io: ApuIo,
+    timers: [Timer; 3],
+    dsp: Dsp,
+    /// SMP cycles since the last DSP sample
+    dsp_cycles: u32,
+    /// Registers at $00F0-$00FF written during the current instruction, one bit per register
+    io_writes: u16,
+    /// Target for writes to read-only registers
+    discarded: u8,
+    /// `true` if reads from $FFC0-$FFFF return the IPL ROM instead of RAM
+    ipl_enabled: bool,
+}
+
+/// Hardware timer counting up to its divider in T0DIV-T2DIV
+///
+/// The 4-bit output counter lives in T0OUT-T2OUT.
+#[derive(Clone, Copy)]
+struct Timer {
+    /// SMP cycles per timer tick
+    period: u32,
+    /// SMP cycles since the last timer tick
+    cycles: u32,
+    enabled: bool,
+    /// Timer ticks since the output counter was last incremented
+    stage: u8,
+}
+
+impl Timer {
+    fn new(period: u32) -> Timer {
+        Timer {
+            period,
+            cycles: 0,
+            enabled: false,
+            stage: 0,
+        }
+    }
 }
 
 impl Bus {
@@ -26,10 +62,19 @@ impl Bus {
         ram[T0OUT] = 0x00;
         ram[T1OUT] = 0x00;
         ram[T2OUT] = 0x00;
-        ram[0xFFC0..].copy_from_slice(&IPL_ROM);
         Bus {
             ram,
             cpu_io: ApuIo::default(),
+            timers: [
+                Timer::new(SLOW_TIMER_PERIOD),
+                Timer::new(SLOW_TIMER_PERIOD),
+                Timer::new(FAST_TIMER_PERIOD),
+            ],
+            dsp: Dsp::default(),
+            dsp_cycles: 0,
+            io_writes: 0,
+            discarded: 0,
+            ipl_enabled: true,
         }
     }
 
@@ -38,6 +83,29 @@ impl Bus {
         &self.ram
     }
 
+    pub fn dsp(&self) -> &Dsp {
+        &self.dsp
+    }
+
+    /// Returns the audio generated since last call, interleaved left and right
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.dsp.take_samples()
+    }
+
+    /// Returns `true` if the IPL ROM is mapped over the top of RAM
+    pub fn ipl_enabled(&self) -> bool {
+        self.ipl_enabled
+    }
+
+    /// Returns the memory as seen by the SMP, RAM with the IPL ROM on top if it is enabled
+    pub fn smp_view(&self) -> Vec<u8> {
+        let mut view = self.ram.to_vec();
+        if self.ipl_enabled {
+            view[IPL_FIRST..].copy_from_slice(&IPL_ROM);
+        }
+        view
+    }
+
     /// Gets IO port values written by APU
     pub fn apu_io(&self) -> ApuIo {
         ApuIo::new(
@@ -48,15 +116,87 @@ impl Bus {
         )
     }
 
-    /// Updates CPU written values in the IO ports
-    pub fn write_cpu_io(&mut self, io: ApuIo) {
-        self.cpu_io = io;
+    /// Updates a CPU written value in the IO ports
+    pub fn write_cpu_io(&mut self, port: u8, value: u8) {
+        self.cpu_io.write(port, value);
+    }
+
+    /// Advances the timers and the DSP by `cycles` SMP cycles
+    ///
+    /// Side effects of register writes are applied first as they are only known after the
+    /// instruction that made them.
+    pub fn tick(&mut self, cycles: u8) {
+        if self.io_writes & (1 << (CONTROL - TEST)) != 0 {
+            self.write_control(self.ram[CONTROL]);
+        }
+        if self.io_writes & (1 << (DSPDATA - TEST)) != 0 {
+            // Upper half of the DSP address space is read-only
+            let addr = self.ram[DSPADDR];
+            if addr < 0x80 {
+                self.dsp.write(addr, self.ram[DSPDATA]);
+            }
+        }
+        self.io_writes = 0;
+
+        for (i, timer) in self.timers.iter_mut().enumerate() {
+            timer.cycles += cycles as u32;
+            while timer.cycles >= timer.period {
+                timer.cycles -= timer.period;
+                if !timer.enabled {
+                    continue;
+                }
+                // Wrapping makes a divider of 0 count to 256
+                timer.stage = timer.stage.wrapping_add(1);
+                if timer.stage == self.ram[T0DIV + i] {
+                    timer.stage = 0;
+                    self.ram[T0OUT + i] = (self.ram[T0OUT + i] + 1) & 0x0F;
+                }
+            }
+        }
+
+        self.dsp_cycles += cycles as u32;
+        while self.dsp_cycles >= DSP_SAMPLE_PERIOD {
+            self.dsp_cycles -= DSP_SAMPLE_PERIOD;
+            self.dsp.step(&mut self.ram);
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        for (i, timer) in self.timers.iter_mut().enumerate() {
+            let enabled = value & (1 << i) != 0;
+            // Timers restart when enabled
+            if enabled && !timer.enabled {
+                timer.stage = 0;
+                self.ram[T0OUT + i] = 0;
+            }
+            timer.enabled = enabled;
+        }
+        if value & CONTROL_PC10 != 0 {
+            self.cpu_io.write(0, 0);
+            self.cpu_io.write(1, 0);
+        }
+        if value & CONTROL_PC32 != 0 {
+            self.cpu_io.write(2, 0);
+            self.cpu_io.write(3, 0);
+        }
+        self.ipl_enabled = value & CONTROL_IPL != 0;
     }
 
     /// Returns a mutable reference to the byte at `addr`
     pub fn mut_byte(&mut self, addr: u16) -> &mut u8 {
-        // TODO: Model smp read-only (T0OUT-T2OUT)
-        &mut self.ram[addr as usize]
+        match addr as usize {
+            // Read-only, read-modify-write instructions still read and clear the counter
+            T0OUT..=T2OUT => {
+                self.discarded = self.ram[addr as usize];
+                self.ram[addr as usize] = 0;
+                &mut self.discarded
+            }
+            TEST..=T2DIV => {
+                self.io_writes |= 1 << (addr as usize - TEST);
+                &mut self.ram[addr as usize]
+            }
+            _ => &mut self.ram[addr as usize],
+        }
     }
 
     /// Writes `value` at `addr` with page wrapping
@@ -67,21 +207,32 @@ impl Bus {
     }
 
     /// Returns byte at `addr`
-    pub fn byte(&self, addr: u16) -> u8 {
-        // TODO: Model write-only (TEST, CONTROL, T0DIV-T2DIV)
+    pub fn byte(&mut self, addr: u16) -> u8 {
         match addr as usize {
+            // Write-only
+            TEST | CONTROL | T0DIV..=T2DIV => 0x00,
+            DSPDATA => self.dsp.read(self.ram[DSPADDR]),
             // Cpu written IO is not in RAM
             CPUIO0..=CPUIO3 => self.cpu_io.read(((addr as u8) & 0xF) - 0x4),
+            // Counters are cleared on read
+            T0OUT..=T2OUT => {
+                let value = self.ram[addr as usize];
+                self.ram[addr as usize] = 0;
+                value
+            }
+            // Writes always go to RAM underneath
+            IPL_FIRST..=0xFFFF if self.ipl_enabled => IPL_ROM[addr as usize - IPL_FIRST],
             _ => self.ram[addr as usize],
         }
     }
 
     // Returns word at `addr` with page wrapping
-    pub fn word(&self, addr: u16) -> u16 {
+    pub fn word(&mut self, addr: u16) -> u16 {
         self.byte(addr) as u16 | ((self.byte(addr.wrapping_add(1)) as u16) << 8)
     }
 }
 
+const IPL_FIRST: usize = 0xFFC0;
 // At $FFC0-$FFFF
 // Takes care of init and (initial) data transfer, though ROMs leverage transfer again by jumping to $FFC0
 const IPL_ROM: [u8; 64] = [
@@ -92,7 +243,6 @@ const IPL_ROM: [u8; 64] = [
 ];
 
 const TEST: usize = 0x00F0;
-#[allow(dead_code)]
 const CONTROL: usize = 0x00F1;
 const DSPADDR: usize = 0x00F2;
 const DSPDATA: usize = 0x00F3;
@@ -110,3 +260,137 @@ const T2DIV: usize = 0x00FC;
 const T0OUT: usize = 0x00FD;
 const T1OUT: usize = 0x00FE;
 const T2OUT: usize = 0x00FF;
+
+// CONTROL bits
+const CONTROL_PC10: u8 = 0b0001_0000;
+const CONTROL_PC32: u8 = 0b0010_0000;
+const CONTROL_IPL: u8 = 0b1000_0000;
+
+/// SMP cycles per DSP sample
+const DSP_SAMPLE_PERIOD: u32 = 32;
+/// SMP cycles per tick of the 8 kHz timers T0 and T1
+const SLOW_TIMER_PERIOD: u32 = 128;
+/// SMP cycles per tick of the 64 kHz timer T2
+const FAST_TIMER_PERIOD: u32 = 16;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `value` to `addr` like the SMP would and applies its side effects
+    fn write(bus: &mut Bus, addr: usize, value: u8) {
+        *bus.mut_byte(addr as u16) = value;
+        bus.tick(0);
+    }
+
+    #[test]
+    fn timer_periods() {
+        let mut bus = Bus::default();
+        write(&mut bus, T0DIV, 1);
+        write(&mut bus, T2DIV, 1);
+        write(&mut bus, CONTROL, 0b101);
+        bus.tick(15);
+        assert_eq!(bus.ram()[T2OUT], 0);
+        bus.tick(1);
+        assert_eq!(bus.ram()[T2OUT], 1);
+        bus.tick(111);
+        assert_eq!(bus.ram()[T0OUT], 0);
+        bus.tick(1);
+        assert_eq!(bus.ram()[T0OUT], 1);
+        assert_eq!(bus.ram()[T2OUT], 8);
+        // Counters are 4 bits
+        for _ in 0..8 {
+            bus.tick(16);
+        }
+        assert_eq!(bus.ram()[T2OUT], 0);
+        // Disabled timer doesn't count
+        assert_eq!(bus.ram()[T1OUT], 0);
+    }
+
+    #[test]
+    fn timer_divider() {
+        let mut bus = Bus::default();
+        write(&mut bus, T2DIV, 3);
+        write(&mut bus, CONTROL, 0b100);
+        bus.tick(47);
+        assert_eq!(bus.ram()[T2OUT], 0);
+        bus.tick(1);
+        assert_eq!(bus.ram()[T2OUT], 1);
+
+        // Zero divides by 256
+        let mut bus = Bus::default();
+        write(&mut bus, T2DIV, 0);
+        write(&mut bus, CONTROL, 0b100);
+        for _ in 0..255 {
+            bus.tick(16);
+        }
+        assert_eq!(bus.ram()[T2OUT], 0);
+        bus.tick(16);
+        assert_eq!(bus.ram()[T2OUT], 1);
+    }
+
+    #[test]
+    fn timer_output_clears_on_read() {
+        let mut bus = Bus::default();
+        write(&mut bus, T2DIV, 1);
+        write(&mut bus, CONTROL, 0b100);
+        bus.tick(48);
+        assert_eq!(bus.byte(T2OUT as u16), 3);
+        assert_eq!(bus.byte(T2OUT as u16), 0);
+
+        // Read-modify-write sees the counter and clears it, the write is dropped
+        bus.tick(32);
+        let value = bus.mut_byte(T2OUT as u16);
+        assert_eq!(*value, 2);
+        *value = 0xFF;
+        assert_eq!(bus.byte(T2OUT as u16), 0);
+    }
+
+    #[test]
+    fn control_restarts_timers() {
+        let mut bus = Bus::default();
+        write(&mut bus, T0DIV, 2);
+        write(&mut bus, CONTROL, 0b001);
+        for _ in 0..3 {
+            bus.tick(128);
+        }
+        assert_eq!(bus.ram()[T0OUT], 1);
+
+        // Writing the enable bit again doesn't restart
+        write(&mut bus, CONTROL, 0b001);
+        bus.tick(128);
+        assert_eq!(bus.ram()[T0OUT], 2);
+
+        // Re-enabling clears the counter and the divider stage
+        write(&mut bus, CONTROL, 0b000);
+        bus.tick(128);
+        write(&mut bus, CONTROL, 0b001);
+        assert_eq!(bus.ram()[T0OUT], 0);
+        bus.tick(128);
+        assert_eq!(bus.ram()[T0OUT], 0);
+        bus.tick(128);
+        assert_eq!(bus.ram()[T0OUT], 1);
+    }
+
+    #[test]
+    fn control_clears_cpu_ports() {
+        let mut bus = Bus::default();
+        for port in 0..4 {
+            bus.write_cpu_io(port, 0x10 + port);
+        }
+        write(&mut bus, CONTROL, CONTROL_PC10);
+        assert_eq!(
+            (0..4)
+                .map(|p| bus.byte((CPUIO0 + p) as u16))
+                .collect::<Vec<_>>(),
+            [0x00, 0x00, 0x12, 0x13]
+        );
+        write(&mut bus, CONTROL, CONTROL_PC32);
+        assert_eq!(
+            (0..4)
+                .map(|p| bus.byte((CPUIO0 + p) as u16))
+                .collect::<Vec<_>>(),
+            [0x00, 0x00, 0x00, 0x00]
+        );
+    }
+}
@@ -0,0 +1,847 @@
+/// Digital signal processor generating the audio output
+///
+/// Produces one stereo sample per step from the eight voices. Timing within a sample isn't
+/// modeled, register changes take effect on the next one.
+pub struct Dsp {
+    regs: [u8; REG_COUNT],
+    voices: [Voice; VOICE_COUNT],
+    /// Global counter driving envelope rates
+    counter: u16,
+    /// KON and KOFF are only polled on every other sample
+    every_other_sample: bool,
+    /// Voices keyed on by writes to KON since it was last polled
+    new_kon: u8,
+    kon: u8,
+    koff: u8,
+    /// 15-bit shift register generating noise
+    noise: i32,
+    /// Last eight samples read from the echo buffer, the FIR filter input
+    echo_hist: [[i32; 2]; ECHO_HIST_SIZE],
+    /// Position of the newest sample in `echo_hist`
+    echo_hist_pos: usize,
+    /// Offset of the current sample in the echo buffer
+    echo_offset: usize,
+    /// Length of the echo buffer in bytes, only updated when the buffer wraps
+    echo_length: usize,
+    /// Interleaved stereo output waiting to be taken
+    samples: Vec<i16>,
+}
+
+#[derive(Clone, Copy)]
+struct Voice {
+    /// Ring of decoded samples, the next four are decoded to `buf_pos`
+    buf: [i16; BRR_BUF_SIZE],
+    buf_pos: usize,
+    /// Position between decoded samples with 12 bits of fraction
+    interp_pos: u16,
+    /// Start of the current BRR block
+    brr_addr: u16,
+    /// Offset of the next sample bytes in the current block
+    brr_offset: u16,
+    /// Samples left before a keyed on voice starts playing
+    kon_delay: u8,
+    env_mode: EnvMode,
+    /// 11-bit envelope level
+    env: i32,
+    /// Envelope level without the rate applied, used by bent line GAIN
+    hidden_env: i32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EnvMode {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+impl Voice {
+    fn default() -> Voice {
+        Voice {
+            buf: [0; BRR_BUF_SIZE],
+            buf_pos: 0,
+            interp_pos: 0,
+            brr_addr: 0,
+            brr_offset: 1,
+            kon_delay: 0,
+            env_mode: EnvMode::Release,
+            env: 0,
+            hidden_env: 0,
+        }
+    }
+}
+
+impl Default for Dsp {
+    fn default() -> Dsp {
+        let mut regs = [0; REG_COUNT];
+        // Soft reset, muted and echo writes disabled
+        regs[FLG] = 0xE0;
+        Dsp {
+            regs,
+            voices: [Voice::default(); VOICE_COUNT],
+            counter: 0,
+            every_other_sample: true,
+            new_kon: 0,
+            kon: 0,
+            koff: 0,
+            noise: 0x4000,
+            echo_hist: [[0; 2]; ECHO_HIST_SIZE],
+            echo_hist_pos: 0,
+            echo_offset: 0,
+            echo_length: 0,
+            samples: Vec::new(),
+        }
+    }
+}
+
+impl Dsp {
+    /// Returns the register at `addr`, $80-$FF mirror $00-$7F
+    pub fn read(&self, addr: u8) -> u8 {
+        self.regs[(addr & 0x7F) as usize]
+    }
+
+    /// Writes `value` to the register at `addr`
+    pub fn write(&mut self, addr: u8, value: u8) {
+        let addr = addr as usize;
+        match addr {
+            KON => {
+                self.new_kon = value;
+                self.regs[addr] = value;
+            }
+            // Any write acknowledges all voices
+            ENDX => self.regs[addr] = 0,
+            _ => self.regs[addr] = value,
+        }
+    }
+
+    /// Returns all registers
+    pub fn regs(&self) -> &[u8] {
+        &self.regs
+    }
+
+    /// Returns the samples generated since last call, interleaved left and right
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Generates the next sample reading BRR data from `ram` and running the echo buffer in it
+    pub fn step(&mut self, ram: &mut [u8]) {
+        self.counter = if self.counter == 0 {
+            COUNTER_RANGE - 1
+        } else {
+            self.counter - 1
+        };
+
+        self.every_other_sample = !self.every_other_sample;
+        if self.every_other_sample {
+            // Voices that were already started don't retrigger
+            self.new_kon &= !self.kon;
+            self.kon = self.new_kon;
+            self.koff = self.regs[KOFF];
+        }
+        if rate_phase(self.counter, self.regs[FLG] & FLG_NOISE_CLOCK) == 0 {
+            let feedback = (self.noise << 13) ^ (self.noise << 14);
+            self.noise = (feedback & 0x4000) ^ (self.noise >> 1);
+        }
+
+        let mut main_out = [0i32; 2];
+        let mut echo_in = [0i32; 2];
+        let mut endx = self.regs[ENDX];
+        let mut prev_output = 0;
+        for v in 0..VOICE_COUNT {
+            let output = self.run_voice(v, ram, &mut endx, prev_output);
+            let echo = self.regs[EON] & (1 << v) != 0;
+            for ch in 0..2 {
+                let vol = self.regs[voice_reg(v, VOLL + ch)] as i8 as i32;
+                let amp = (output * vol) >> 7;
+                main_out[ch] = clamp16(main_out[ch] + amp);
+                if echo {
+                    echo_in[ch] = clamp16(echo_in[ch] + amp);
+                }
+            }
+            prev_output = output;
+        }
+        self.regs[ENDX] = endx;
+
+        let echo_out = self.run_echo(ram, echo_in);
+
+        let mute = self.regs[FLG] & FLG_MUTE != 0;
+        for ch in 0..2 {
+            let main_vol = self.regs[MVOLL + ch * 0x10] as i8 as i32;
+            let echo_vol = self.regs[EVOLL + ch * 0x10] as i8 as i32;
+            let sample = if mute {
+                0
+            } else {
+                clamp16(((main_out[ch] * main_vol) >> 7) + ((echo_out[ch] * echo_vol) >> 7))
+            };
+            if self.samples.len() < MAX_BUFFERED_SAMPLES {
+                self.samples.push(sample as i16);
+            }
+        }
+    }
+
+    /// Feeds `echo_in` to the echo buffer in `ram` and returns the filtered echo output
+    fn run_echo(&mut self, ram: &mut [u8], echo_in: [i32; 2]) -> [i32; 2] {
+        let echo_addr = ((self.regs[ESA] as usize) << 8) + self.echo_offset;
+
+        self.echo_hist_pos = (self.echo_hist_pos + 1) % ECHO_HIST_SIZE;
+        for ch in 0..2 {
+            // Buffer holds 15-bit samples
+            self.echo_hist[self.echo_hist_pos][ch] =
+                (ram_word(ram, echo_addr + ch * 2) as i16 >> 1) as i32;
+        }
+
+        let mut echo_out = [0i32; 2];
+        for (ch, out) in echo_out.iter_mut().enumerate() {
+            // FIR0 is applied to the oldest sample
+            let fir = |i: usize| {
+                let sample = self.echo_hist[(self.echo_hist_pos + 1 + i) % ECHO_HIST_SIZE][ch];
+                (sample * self.regs[FIR + i * 0x10] as i8 as i32) >> 6
+            };
+            // The hardware wraps the sum before the last tap and clamps after it
+            let sum = (0..ECHO_HIST_SIZE - 1).map(fir).sum::<i32>() as i16 as i32;
+            *out = clamp16(sum + fir(ECHO_HIST_SIZE - 1)) & !1;
+        }
+
+        if self.regs[FLG] & FLG_ECHO_DISABLE == 0 {
+            let feedback = self.regs[EFB] as i8 as i32;
+            for ch in 0..2 {
+                let value = clamp16(echo_in[ch] + ((echo_out[ch] * feedback) >> 7)) & !1;
+                let addr = (echo_addr + ch * 2) & 0xFFFF;
+                ram[addr] = value as u8;
+                ram[(addr + 1) & 0xFFFF] = (value >> 8) as u8;
+            }
+        }
+
+        // New delay only takes effect once the current buffer wraps
+        if self.echo_offset == 0 {
+            self.echo_length = (self.regs[EDL] & 0x0F) as usize * ECHO_BLOCK_SIZE;
+        }
+        self.echo_offset += 4;
+        if self.echo_offset >= self.echo_length {
+            self.echo_offset = 0;
+        }
+
+        echo_out
+    }
+
+    /// Runs voice `v` for a sample and returns its output
+    ///
+    /// `prev_output` is the output of the previous voice, used for pitch modulation.
+    fn run_voice(&mut self, v: usize, ram: &[u8], endx: &mut u8, prev_output: i32) -> i32 {
+        let vbit = 1 << v;
+        let dir_entry =
+            ((self.regs[DIR] as usize) << 8) + self.regs[voice_reg(v, SRCN)] as usize * 4;
+        let mut pitch = ((self.regs[voice_reg(v, PITCHL)] as i32)
+            | ((self.regs[voice_reg(v, PITCHH)] as i32) << 8))
+            & 0x3FFF;
+        // Voice 0 has no previous voice to modulate it
+        if v > 0 && self.regs[PMON] & vbit != 0 {
+            pitch += ((prev_output >> 5) * pitch) >> 10;
+        }
+
+        let voice = &mut self.voices[v];
+        let mut header = ram[voice.brr_addr as usize];
+        if voice.kon_delay > 0 {
+            if voice.kon_delay == 5 {
+                voice.brr_addr = ram_word(ram, dir_entry);
+                voice.brr_offset = 1;
+                voice.buf_pos = 0;
+                // Header is ignored on this sample
+                header = 0;
+                *endx &= !vbit;
+            }
+            voice.env = 0;
+            voice.hidden_env = 0;
+            // Decoding is only enabled for the last three samples to fill the buffer
+            voice.kon_delay -= 1;
+            voice.interp_pos = if voice.kon_delay & 3 != 0 { 0x4000 } else { 0 };
+            // Pitch is never added during KON
+            pitch = 0;
+        }
+
+        let sample = if self.regs[NON] & vbit != 0 {
+            (self.noise * 2) as i16 as i32
+        } else {
+            voice.interpolate()
+        };
+        let output = ((sample * voice.env) >> 11) & !1;
+        self.regs[voice_reg(v, ENVX)] = (voice.env >> 4) as u8;
+        self.regs[voice_reg(v, OUTX)] = (output >> 8) as u8;
+
+        // End of sample without a loop or a soft reset silence the voice right away
+        if self.regs[FLG] & FLG_RESET != 0 || header & 0x03 == BRR_END {
+            voice.env_mode = EnvMode::Release;
+            voice.env = 0;
+        }
+        if self.every_other_sample {
+            if self.koff & vbit != 0 {
+                voice.env_mode = EnvMode::Release;
+            }
+            if self.kon & vbit != 0 {
+                voice.kon_delay = 5;
+                voice.env_mode = EnvMode::Attack;
+            }
+        }
+        if voice.kon_delay == 0 {
+            let adsr1 = self.regs[voice_reg(v, ADSR1)];
+            let adsr2 = self.regs[voice_reg(v, ADSR2)];
+            let gain = self.regs[voice_reg(v, GAIN)];
+            voice.run_envelope(self.counter, adsr1, adsr2, gain);
+        }
+
+        if voice.interp_pos >= 0x4000 {
+            voice.decode_brr(ram, header);
+            voice.brr_offset += 2;
+            if voice.brr_offset >= BRR_BLOCK_SIZE {
+                voice.brr_addr = voice.brr_addr.wrapping_add(BRR_BLOCK_SIZE);
+                if header & BRR_END != 0 {
+                    voice.brr_addr = ram_word(ram, dir_entry + 2);
+                    *endx |= vbit;
+                }
+                voice.brr_offset = 1;
+            }
+        }
+        // Keep from getting too far ahead
+        voice.interp_pos = ((voice.interp_pos & 0x3FFF) as i32 + pitch).min(0x7FFF) as u16;
+
+        output
+    }
+}
+
+impl Voice {
+    /// Returns the Gaussian interpolation of the four samples around the current position
+    fn interpolate(&self) -> i32 {
+        let offset = ((self.interp_pos >> 4) & 0xFF) as usize;
+        let fwd = 255 - offset;
+        let rev = offset;
+        let pos = self.buf_pos + (self.interp_pos >> 12) as usize;
+        let sample = |i: usize| self.buf[(pos + i) % BRR_BUF_SIZE] as i32;
+
+        let mut out = (GAUSS[fwd] as i32 * sample(0)) >> 11;
+        out += (GAUSS[fwd + 256] as i32 * sample(1)) >> 11;
+        out += (GAUSS[rev + 256] as i32 * sample(2)) >> 11;
+        // The hardware wraps here before adding the last sample
+        out = out as i16 as i32;
+        out += (GAUSS[rev] as i32 * sample(3)) >> 11;
+        clamp16(out) & !1
+    }
+
+    /// Decodes the next four samples of the current block with `header`
+    fn decode_brr(&mut self, ram: &[u8], header: u8) {
+        let shift = header >> 4;
+        let filter = (header >> 2) & 0x03;
+        let addr = self.brr_addr.wrapping_add(self.brr_offset);
+        let nybbles =
+            ((ram[addr as usize] as u16) << 8) | ram[addr.wrapping_add(1) as usize] as u16;
+
+        for i in 0..4 {
+            // Sign extend the nybble
+            let mut s = ((nybbles << (i * 4)) as i16 >> 12) as i32;
+            s = if shift <= 12 {
+                (s << shift) >> 1
+            } else if s < 0 {
+                // Invalid shifts act like 12 on negative samples and zero the rest
+                -2048
+            } else {
+                0
+            };
+
+            let p1 = self.buf[(self.buf_pos + BRR_BUF_SIZE - 1) % BRR_BUF_SIZE] as i32;
+            let p2 = (self.buf[(self.buf_pos + BRR_BUF_SIZE - 2) % BRR_BUF_SIZE] as i32) >> 1;
+            match filter {
+                1 => {
+                    s += p1 >> 1;
+                    s += (-p1) >> 5;
+                }
+                2 => {
+                    s += p1 - p2;
+                    s += p2 >> 4;
+                    s += (p1 * -3) >> 6;
+                }
+                3 => {
+                    s += p1 - p2;
+                    s += (p1 * -13) >> 7;
+                    s += (p2 * 3) >> 4;
+                }
+                _ => (),
+            }
+            // Samples are stored as 15 bits
+            self.buf[self.buf_pos] = (clamp16(s) * 2) as i16;
+            self.buf_pos = (self.buf_pos + 1) % BRR_BUF_SIZE;
+        }
+    }
+
+    /// Advances the envelope if its rate triggers on `counter`
+    fn run_envelope(&mut self, counter: u16, adsr1: u8, adsr2: u8, gain: u8) {
+        let mut env = self.env;
+        if self.env_mode == EnvMode::Release {
+            self.env = (env - 0x8).max(0);
+            return;
+        }
+
+        let rate;
+        // Sustain level is compared against this, even in GAIN mode
+        let mut env_data = adsr2;
+        if adsr1 & ADSR_ENABLE != 0 {
+            match self.env_mode {
+                EnvMode::Attack => {
+                    rate = (adsr1 & 0x0F) * 2 + 1;
+                    env += if rate < 31 { 0x20 } else { 0x400 };
+                }
+                EnvMode::Decay => {
+                    env -= 1;
+                    env -= env >> 8;
+                    rate = ((adsr1 >> 3) & 0x0E) + 0x10;
+                }
+                _ => {
+                    env -= 1;
+                    env -= env >> 8;
+                    rate = adsr2 & 0x1F;
+                }
+            }
+        } else {
+            env_data = gain;
+            let mode = gain >> 5;
+            if mode < 4 {
+                // Direct
+                env = gain as i32 * 0x10;
+                rate = 31;
+            } else {
+                rate = gain & 0x1F;
+                match mode {
+                    // Linear decrease
+                    4 => env -= 0x20,
+                    // Exponential decrease
+                    5 => {
+                        env -= 1;
+                        env -= env >> 8;
+                    }
+                    // Linear and bent line increase
+                    _ => {
+                        env += 0x20;
+                        if mode == 7 && self.hidden_env >= 0x600 {
+                            env += 0x8 - 0x20;
+                        }
+                    }
+                }
+            }
+        }
+
+        if env >> 8 == (env_data >> 5) as i32 && self.env_mode == EnvMode::Decay {
+            self.env_mode = EnvMode::Sustain;
+        }
+        self.hidden_env = env;
+        if !(0..=0x7FF).contains(&env) {
+            env = if env < 0 { 0 } else { 0x7FF };
+            if self.env_mode == EnvMode::Attack {
+                self.env_mode = EnvMode::Decay;
+            }
+        }
+        if rate_phase(counter, rate) == 0 {
+            self.env = env;
+        }
+    }
+}
+
+/// Returns the phase of `counter` for `rate`, things running at it update when this is 0
+fn rate_phase(counter: u16, rate: u8) -> u16 {
+    let rate = rate as usize;
+    (counter + COUNTER_OFFSETS[rate]) % COUNTER_RATES[rate]
+}
+
+fn clamp16(value: i32) -> i32 {
+    value.max(i16::MIN as i32).min(i16::MAX as i32)
+}
+
+fn ram_word(ram: &[u8], addr: usize) -> u16 {
+    (ram[addr & 0xFFFF] as u16) | ((ram[(addr + 1) & 0xFFFF] as u16) << 8)
+}
+
+/// Returns the address of voice register `reg` of voice `v`
+fn voice_reg(v: usize, reg: usize) -> usize {
+    (v << 4) | reg
+}
+
+const REG_COUNT: usize = 128;
+const VOICE_COUNT: usize = 8;
+
+/// Samples per second
+pub const SAMPLE_RATE: u32 = 32_000;
+/// Stereo samples kept at most if nobody takes them, newer ones are dropped
+const MAX_BUFFERED_SAMPLES: usize = SAMPLE_RATE as usize * 2;
+
+// Voice registers, added to $x0 of the voice
+const VOLL: usize = 0x0;
+const PITCHL: usize = 0x2;
+const PITCHH: usize = 0x3;
+const SRCN: usize = 0x4;
+const ADSR1: usize = 0x5;
+const ADSR2: usize = 0x6;
+const GAIN: usize = 0x7;
+const ENVX: usize = 0x8;
+const OUTX: usize = 0x9;
+
+// Global registers
+const MVOLL: usize = 0x0C;
+const EVOLL: usize = 0x2C;
+const KON: usize = 0x4C;
+const KOFF: usize = 0x5C;
+const FLG: usize = 0x6C;
+const ENDX: usize = 0x7C;
+const EFB: usize = 0x0D;
+const PMON: usize = 0x2D;
+const NON: usize = 0x3D;
+const EON: usize = 0x4D;
+const DIR: usize = 0x5D;
+const ESA: usize = 0x6D;
+const EDL: usize = 0x7D;
+/// FIR coefficients are at $xF
+const FIR: usize = 0x0F;
+
+// FLG bits
+const FLG_RESET: u8 = 0b1000_0000;
+const FLG_MUTE: u8 = 0b0100_0000;
+const FLG_ECHO_DISABLE: u8 = 0b0010_0000;
+const FLG_NOISE_CLOCK: u8 = 0b0001_1111;
+
+/// Bytes of echo buffer per step of EDL, 16 ms of stereo samples
+const ECHO_BLOCK_SIZE: usize = 0x800;
+/// Taps in the FIR filter
+const ECHO_HIST_SIZE: usize = 8;
+
+const ADSR_ENABLE: u8 = 0b1000_0000;
+
+/// Bytes in a BRR block, a header and 16 4-bit samples
+const BRR_BLOCK_SIZE: u16 = 9;
+/// Decoded samples kept for interpolation
+const BRR_BUF_SIZE: usize = 12;
+// BRR header flags
+const BRR_END: u8 = 0b01;
+
+/// Period of the global counter
+const COUNTER_RANGE: u16 = 2048 * 5 * 3;
+/// Samples between updates of each rate, rate 0 never fires
+const COUNTER_RATES: [u16; 32] = [
+    COUNTER_RANGE + 1,
+    2048,
+    1536,
+    1280,
+    1024,
+    768,
+    640,
+    512,
+    384,
+    320,
+    256,
+    192,
+    160,
+    128,
+    96,
+    80,
+    64,
+    48,
+    40,
+    32,
+    24,
+    20,
+    16,
+    12,
+    10,
+    8,
+    6,
+    5,
+    4,
+    3,
+    2,
+    1,
+];
+const COUNTER_OFFSETS: [u16; 32] = [
+    1, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040,
+    536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 0, 0,
+];
+
+/// Gaussian interpolation kernel
+#[rustfmt::skip]
+const GAUSS: [i16; 512] = [
+       0,    0,    0,    0,    0,    0,    0,    0,    0,    0,    0,    0,    0,    0,    0,    0,
+       1,    1,    1,    1,    1,    1,    1,    1,    1,    1,    1,    2,    2,    2,    2,    2,
+       2,    2,    3,    3,    3,    3,    3,    4,    4,    4,    4,    4,    5,    5,    5,    5,
+       6,    6,    6,    6,    7,    7,    7,    8,    8,    8,    9,    9,    9,   10,   10,   10,
+      11,   11,   11,   12,   12,   13,   13,   14,   14,   15,   15,   15,   16,   16,   17,   17,
+      18,   19,   19,   20,   20,   21,   21,   22,   23,   23,   24,   24,   25,   26,   27,   27,
+      28,   29,   29,   30,   31,   32,   32,   33,   34,   35,   36,   36,   37,   38,   39,   40,
+      41,   42,   43,   44,   45,   46,   47,   48,   49,   50,   51,   52,   53,   54,   55,   56,
+      58,   59,   60,   61,   62,   64,   65,   66,   67,   69,   70,   71,   73,   74,   76,   77,
+      78,   80,   81,   83,   84,   86,   87,   89,   90,   92,   94,   95,   97,   99,  100,  102,
+     104,  106,  107,  109,  111,  113,  115,  117,  118,  120,  122,  124,  126,  128,  130,  132,
+     134,  137,  139,  141,  143,  145,  147,  150,  152,  154,  156,  159,  161,  163,  166,  168,
+     171,  173,  175,  178,  180,  183,  186,  188,  191,  193,  196,  199,  201,  204,  207,  210,
+     212,  215,  218,  221,  224,  227,  230,  233,  236,  239,  242,  245,  248,  251,  254,  257,
+     260,  263,  267,  270,  273,  276,  280,  283,  286,  290,  293,  297,  300,  304,  307,  311,
+     314,  318,  321,  325,  328,  332,  336,  339,  343,  347,  351,  354,  358,  362,  366,  370,
+     374,  378,  381,  385,  389,  393,  397,  401,  405,  410,  414,  418,  422,  426,  430,  434,
+     439,  443,  447,  451,  456,  460,  464,  469,  473,  477,  482,  486,  491,  495,  499,  504,
+     508,  513,  517,  522,  527,  531,  536,  540,  545,  550,  554,  559,  563,  568,  573,  577,
+     582,  587,  592,  596,  601,  606,  611,  615,  620,  625,  630,  635,  640,  644,  649,  654,
+     659,  664,  669,  674,  678,  683,  688,  693,  698,  703,  708,  713,  718,  723,  728,  732,
+     737,  742,  747,  752,  757,  762,  767,  772,  777,  782,  787,  792,  797,  802,  806,  811,
+     816,  821,  826,  831,  836,  841,  846,  851,  855,  860,  865,  870,  875,  880,  884,  889,
+     894,  899,  904,  908,  913,  918,  923,  927,  932,  937,  941,  946,  951,  955,  960,  965,
+     969,  974,  978,  983,  988,  992,  997, 1001, 1005, 1010, 1014, 1019, 1023, 1027, 1032, 1036,
+    1040, 1045, 1049, 1053, 1057, 1061, 1066, 1070, 1074, 1078, 1082, 1086, 1090, 1094, 1098, 1102,
+    1106, 1109, 1113, 1117, 1121, 1125, 1128, 1132, 1136, 1139, 1143, 1146, 1150, 1153, 1157, 1160,
+    1164, 1167, 1170, 1174, 1177, 1180, 1183, 1186, 1190, 1193, 1196, 1199, 1202, 1205, 1207, 1210,
+    1213, 1216, 1219, 1221, 1224, 1227, 1229, 1232, 1234, 1237, 1239, 1241, 1244, 1246, 1248, 1251,
+    1253, 1255, 1257, 1259, 1261, 1263, 1265, 1267, 1269, 1270, 1272, 1274, 1275, 1277, 1279, 1280,
+    1282, 1283, 1284, 1286, 1287, 1288, 1290, 1291, 1292, 1293, 1294, 1295, 1296, 1297, 1297, 1298,
+    1299, 1300, 1300, 1301, 1302, 1302, 1303, 1303, 1303, 1304, 1304, 1304, 1304, 1304, 1305, 1305,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes `bytes` with `header` after the samples in `prev`, oldest first
+    fn decode(header: u8, bytes: [u8; 2], prev: [i16; 2]) -> [i16; 4] {
+        let mut voice = Voice::default();
+        voice.buf[0] = prev[0];
+        voice.buf[1] = prev[1];
+        voice.buf_pos = 2;
+        let ram = [header, bytes[0], bytes[1]];
+        voice.decode_brr(&ram, header);
+        let mut samples = [0; 4];
+        samples.copy_from_slice(&voice.buf[2..6]);
+        samples
+    }
+
+    /// Returns the first counter value `rate` fires on
+    fn fire(rate: u8) -> u16 {
+        (0..COUNTER_RANGE)
+            .find(|&c| rate_phase(c, rate) == 0)
+            .unwrap()
+    }
+
+    /// Runs the envelope of a voice at `env` in `mode` once on `counter`
+    fn envelope(mode: EnvMode, env: i32, counter: u16, adsr1: u8, adsr2: u8, gain: u8) -> Voice {
+        let mut voice = Voice::default();
+        voice.env_mode = mode;
+        voice.env = env;
+        voice.hidden_env = env;
+        voice.run_envelope(counter, adsr1, adsr2, gain);
+        voice
+    }
+
+    /// Returns a running DSP and RAM with sample 0 at $0300 in a directory at $0200
+    fn setup() -> (Dsp, Vec<u8>) {
+        let mut dsp = Dsp::default();
+        let mut ram = vec![0; 0x10000];
+        dsp.write(FLG as u8, 0);
+        dsp.write(DIR as u8, 0x02);
+        ram[0x0200..0x0204].copy_from_slice(&[0x00, 0x03, 0x00, 0x03]);
+        // Looping block of loud samples
+        ram[0x0300] = 0xC2;
+        for byte in &mut ram[0x0301..0x0309] {
+            *byte = 0x77;
+        }
+        (dsp, ram)
+    }
+
+    #[test]
+    fn brr_filters() {
+        assert_eq!(
+            decode(0xC0, [0x78, 0xF0], [0, 0]),
+            [28672, -32768, -4096, 0]
+        );
+        assert_eq!(decode(0x14, [0x01, 0x0F], [0, 1000]), [936, 878, 822, 768]);
+        assert_eq!(
+            decode(0x48, [0x10, 0x20], [-600, 1000]),
+            [2484, 3796, 4938, 5852]
+        );
+        assert_eq!(
+            decode(0x2C, [0x00, 0x00], [-600, 1000]),
+            [2282, 3286, 4048, 4602]
+        );
+    }
+
+    #[test]
+    fn brr_range() {
+        // Invalid shift
+        assert_eq!(decode(0xD0, [0x78, 0xF0], [0, 0]), [0, -4096, -4096, 0]);
+        // Filter output is clamped to 16 bits before being stored as 15
+        assert_eq!(
+            decode(0xC8, [0x77, 0x00], [-32000, 32000]),
+            [-2, -1332, -2540, -3594]
+        );
+    }
+
+    #[test]
+    fn adsr() {
+        let voice = envelope(EnvMode::Attack, 0, fire(31), 0x8F, 0, 0);
+        assert_eq!(voice.env, 0x400);
+        let voice = envelope(EnvMode::Attack, 0x400, fire(31), 0x8F, 0, 0);
+        assert_eq!(voice.env, 0x7FF);
+        assert!(voice.env_mode == EnvMode::Decay);
+        let voice = envelope(EnvMode::Attack, 0, fire(29), 0x8E, 0, 0);
+        assert_eq!(voice.env, 0x20);
+
+        // Decay ends at the sustain level
+        let voice = envelope(EnvMode::Decay, 0x7FF, fire(30), 0xFF, 0xE0, 0);
+        assert_eq!(voice.env, 0x7F7);
+        assert!(voice.env_mode == EnvMode::Sustain);
+        let voice = envelope(EnvMode::Sustain, 0x7F7, fire(31), 0xFF, 0xFF, 0);
+        assert_eq!(voice.env, 0x7EF);
+        assert!(voice.env_mode == EnvMode::Sustain);
+
+        // Release ignores the rate
+        let voice = envelope(EnvMode::Release, 0x100, 1, 0xFF, 0xFF, 0);
+        assert_eq!(voice.env, 0xF8);
+        let voice = envelope(EnvMode::Release, 0x4, 1, 0xFF, 0xFF, 0);
+        assert_eq!(voice.env, 0);
+    }
+
+    #[test]
+    fn gain() {
+        // Direct
+        assert_eq!(envelope(EnvMode::Attack, 0, 1, 0, 0, 0x40).env, 0x400);
+        // Linear decrease
+        assert_eq!(
+            envelope(EnvMode::Attack, 0x400, fire(31), 0, 0, 0x9F).env,
+            0x3E0
+        );
+        assert_eq!(envelope(EnvMode::Attack, 0x10, fire(31), 0, 0, 0x9F).env, 0);
+        // Exponential decrease
+        assert_eq!(
+            envelope(EnvMode::Attack, 0x400, fire(31), 0, 0, 0xBF).env,
+            0x3FC
+        );
+        // Linear increase
+        assert_eq!(
+            envelope(EnvMode::Attack, 0x400, fire(31), 0, 0, 0xDF).env,
+            0x420
+        );
+        assert_eq!(envelope(EnvMode::Attack, 0x7F0, 31, 0, 0, 0xDF).env, 0x7FF);
+        // Bent line
+        assert_eq!(
+            envelope(EnvMode::Attack, 0x500, fire(31), 0, 0, 0xFF).env,
+            0x520
+        );
+        assert_eq!(
+            envelope(EnvMode::Attack, 0x600, fire(31), 0, 0, 0xFF).env,
+            0x608
+        );
+
+        // Rate 0 never fires
+        let mut voice = Voice::default();
+        voice.env_mode = EnvMode::Attack;
+        for counter in 0..COUNTER_RANGE {
+            voice.run_envelope(counter, 0, 0, 0xC0);
+        }
+        assert_eq!(voice.env, 0);
+    }
+
+    #[test]
+    fn kon_koff() {
+        let (mut dsp, mut ram) = setup();
+        dsp.write(voice_reg(0, GAIN) as u8, 0x7F);
+        dsp.regs[ENDX] = 0x01;
+        dsp.write(KON as u8, 0x01);
+
+        // KON is polled on the second sample and the voice starts five samples later
+        for _ in 0..7 {
+            dsp.step(&mut ram);
+            assert_eq!(dsp.read(voice_reg(0, ENVX) as u8), 0);
+        }
+        assert_eq!(dsp.read(ENDX as u8), 0);
+        dsp.step(&mut ram);
+        assert_eq!(dsp.read(voice_reg(0, ENVX) as u8), 0x7F);
+        assert_ne!(dsp.read(voice_reg(0, OUTX) as u8), 0);
+
+        dsp.step(&mut ram);
+        dsp.step(&mut ram);
+        dsp.write(KOFF as u8, 0x01);
+        // KOFF waits for the next poll
+        dsp.step(&mut ram);
+        dsp.step(&mut ram);
+        assert_eq!(dsp.read(voice_reg(0, ENVX) as u8), 0x7F);
+        dsp.step(&mut ram);
+        assert_eq!(dsp.read(voice_reg(0, ENVX) as u8), 0x7E);
+        for _ in 0..253 {
+            dsp.step(&mut ram);
+        }
+        assert_eq!(dsp.read(voice_reg(0, ENVX) as u8), 0);
+    }
+
+    #[test]
+    fn echo_feedback() {
+        let (mut dsp, mut ram) = setup();
+        dsp.write(ESA as u8, 0x80);
+        dsp.write(EFB as u8, 0x40);
+        dsp.write((FIR + 0x70) as u8, 0x40);
+        dsp.write(EVOLL as u8, 0x7F);
+        dsp.write((EVOLL + 0x10) as u8, 0x7F);
+        ram[0x8000..0x8004].copy_from_slice(&[0x00, 0x10, 0x00, 0xF0]);
+
+        dsp.step(&mut ram);
+        assert_eq!(dsp.take_samples(), vec![2032, -2032]);
+        // Halved by feedback, the last bit is always clear
+        assert_eq!(&ram[0x8000..0x8004], &[0x00, 0x04, 0x00, 0xFC]);
+        dsp.step(&mut ram);
+        assert_eq!(&ram[0x8000..0x8004], &[0x00, 0x01, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn echo_fir() {
+        let (mut dsp, mut ram) = setup();
+        dsp.write(ESA as u8, 0x80);
+        dsp.write(FIR as u8, 0x40);
+        dsp.write(EVOLL as u8, 0x7F);
+        ram[0x8000..0x8002].copy_from_slice(&[0x00, 0x10]);
+
+        // FIR0 taps the sample read seven samples earlier
+        for _ in 0..9 {
+            dsp.step(&mut ram);
+        }
+        let left = dsp
+            .take_samples()
+            .iter()
+            .step_by(2)
+            .copied()
+            .collect::<Vec<_>>();
+        assert_eq!(left, vec![0, 0, 0, 0, 0, 0, 0, 2032, 0]);
+    }
+
+    #[test]
+    fn echo_buffer() {
+        let (mut dsp, mut ram) = setup();
+        dsp.write(ESA as u8, 0x80);
+        dsp.write(EDL as u8, 1);
+        dsp.write(EON as u8, 0x01);
+        dsp.write(VOLL as u8, 0x7F);
+        dsp.write(voice_reg(0, GAIN) as u8, 0x7F);
+        dsp.write(KON as u8, 0x01);
+        for _ in 0..16 {
+            dsp.step(&mut ram);
+        }
+        // Voice output is written to consecutive frames from ESA
+        assert_ne!(ram_word(&ram, 0x8000 + 15 * 4), 0);
+        assert_eq!(ram_word(&ram, 0x8000 + 16 * 4), 0);
+        assert_eq!(ram_word(&ram, 0x8002 + 15 * 4), 0);
+    }
+
+    #[test]
+    fn echo_write_disable() {
+        let (mut dsp, mut ram) = setup();
+        dsp.write(FLG as u8, FLG_ECHO_DISABLE);
+        dsp.write(ESA as u8, 0x80);
+        dsp.write(EON as u8, 0x01);
+        dsp.write(VOLL as u8, 0x7F);
+        dsp.write(voice_reg(0, GAIN) as u8, 0x7F);
+        dsp.write(KON as u8, 0x01);
+        ram[0x8000..0x8004].copy_from_slice(&[0x00, 0x10, 0x00, 0xF0]);
+        for _ in 0..16 {
+            dsp.step(&mut ram);
+        }
+        assert_eq!(&ram[0x8000..0x8004], &[0x00, 0x10, 0x00, 0xF0]);
+    }
+}
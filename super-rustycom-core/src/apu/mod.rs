@@ -1,13 +1,20 @@
 pub mod bus;
+pub mod dsp;
 pub mod smp;
 
 use self::bus::Bus;
 use self::smp::Spc700;
-use super::apu_io::ApuIo;
+
+/// Master clock ticks per second
+const MASTER_CLOCK: i64 = 21_477_272;
+/// SMP cycles per second
+const SMP_CLOCK: i64 = 1_024_000;
 
 pub struct Apu {
     pub smp: Spc700,
     pub bus: Bus,
+    /// SMP cycles the APU is behind in units of 1 / `MASTER_CLOCK`, negative if it is ahead
+    cycle_debt: i64,
 }
 
 impl Apu {
@@ -15,14 +22,50 @@ impl Apu {
         Apu {
             smp: Spc700::default(),
             bus: Bus::default(),
+            cycle_debt: 0,
+        }
+    }
+
+    /// Runs the APU for the SMP cycles that fit in `ticks` master clock ticks
+    ///
+    /// Instructions aren't split so the APU can end up slightly ahead, which is evened out on the
+    /// next run.
+    pub fn run(&mut self, ticks: u32) {
+        self.cycle_debt += ticks as i64 * SMP_CLOCK;
+        while self.cycle_debt >= MASTER_CLOCK {
+            match self.smp.step(&mut self.bus) {
+                Some(cycles) => {
+                    self.bus.tick(cycles);
+                    self.cycle_debt -= cycles as i64 * MASTER_CLOCK;
+                }
+                None => {
+                    // DSP and timers keep running while the SMP is halted
+                    let cycles = (self.cycle_debt / MASTER_CLOCK).min(u8::MAX as i64);
+                    self.bus.tick(cycles as u8);
+                    self.cycle_debt -= cycles * MASTER_CLOCK;
+                }
+            }
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halted_smp_runs_timers_and_dsp() {
+        let mut apu = Apu::default();
+        // Map RAM over the IPL ROM, start T2 and put SLEEP at the reset vector
+        *apu.bus.mut_byte(0x00FC) = 1;
+        apu.bus.tick(0);
+        *apu.bus.mut_byte(0x00F1) = 0x04;
+        apu.bus.tick(0);
+        *apu.bus.mut_byte(0xFFC0) = 0xEF;
 
-    /// Steps the entire APU and returns SMP cycles evaluated as well as the state of the IO ports
-    pub fn step(&mut self, io: ApuIo) -> (Option<u8>, ApuIo) {
-        self.bus.write_cpu_io(io);
-        let cycles = self.smp.step(&mut self.bus);
-        let io = self.bus.apu_io();
-        (cycles, io)
+        // 160 SMP cycles
+        apu.run(3356);
+        assert_eq!(apu.bus.ram()[0x00FF], 10);
+        assert_eq!(apu.bus.take_samples().len(), 5 * 2);
     }
 }
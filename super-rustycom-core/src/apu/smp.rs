@@ -156,33 +156,37 @@ impl Spc700 {
         }
         macro_rules! mut_ind_y {
             // [ad]+Y
-            ($addr:expr ) => {
+            ($addr:expr ) => {{
                 // TODO: Does this honor current direct page?
-                bus.mut_byte(bus.word($addr as u16).wrapping_add(self.y as u16))
-            };
+                let addr = bus.word($addr as u16).wrapping_add(self.y as u16);
+                bus.mut_byte(addr)
+            }};
         }
         macro_rules! ind_y {
             // [ad]+Y
-            ($addr:expr ) => {
+            ($addr:expr ) => {{
                 // TODO: Does this honor current direct page?
-                bus.byte(bus.word($addr as u16).wrapping_add(self.y as u16))
-            };
+                let addr = bus.word($addr as u16).wrapping_add(self.y as u16);
+                bus.byte(addr)
+            }};
         }
         macro_rules! mut_x_ind {
             // [ad+X]
-            ($addr:expr ) => {
+            ($addr:expr ) => {{
                 // TODO: Does this honor current direct page?
                 // TODO: Does this really with page?
-                bus.mut_byte(bus.word($addr.wrapping_add(self.x) as u16))
-            };
+                let addr = bus.word($addr.wrapping_add(self.x) as u16);
+                bus.mut_byte(addr)
+            }};
         }
         macro_rules! x_ind {
             // [ad+X]
-            ($addr:expr ) => {
+            ($addr:expr ) => {{
                 // TODO: Does this honor current direct page?
                 // TODO: Does this really with page?
-                bus.byte(bus.word($addr.wrapping_add(self.x) as u16))
-            };
+                let addr = bus.word($addr.wrapping_add(self.x) as u16);
+                bus.byte(addr)
+            }};
         }
 
         // Ops
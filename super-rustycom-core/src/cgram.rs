@@ -29,6 +29,16 @@ impl Cgram {
         self.increment();
     }
 
+    /// Writes `value` to color `index` instead of the current address, which is still
+    /// incremented
+    ///
+    /// This is where writes land during active display, as the PPU has taken over the address.
+    pub fn write_data_at(&mut self, index: u8, value: u8) {
+        let addr = ((index as usize) << 1) | (self.odd_access as usize);
+        self.mem[addr] = value;
+        self.increment();
+    }
+
     pub fn read_data(&mut self) -> u8 {
         let value = self.peek_data();
         self.increment();
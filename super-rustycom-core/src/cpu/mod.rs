@@ -60,6 +60,12 @@ pub struct W65c816s {
     stopped: bool,
     /// `true` if waiting until interrupted
     waiting: bool,
+    /// Current state of the NMI input, used to detect edges
+    nmi_line: bool,
+    /// `true` if an edge has been detected on the NMI input and it hasn't been serviced yet
+    nmi_pending: bool,
+    /// Current state of the IRQ input
+    irq_line: bool,
 }
 
 impl W65c816s {
@@ -68,12 +74,12 @@ impl W65c816s {
     /// The processor starts in emulation mode, `PC` is set to the reset vector,
     /// `S` is set to `$01FF`, `A`, `X` and `Y` are 8bits wide
     /// and interrupts are disabled. Other values are zeroed.
-    pub fn new(abus: &mut ABus) -> W65c816s {
+    pub fn new(abus: &ABus) -> W65c816s {
         W65c816s {
             a: 0x00,
             x: 0x00,
             y: 0x00,
-            pc: abus.page_wrapping_cpu_read16(RESET8),
+            pc: abus.page_wrapping_cpu_peek16(RESET8),
             s: 0x01FF,
             p: StatusReg::new(),
             d: 0x00,
@@ -82,11 +88,35 @@ impl W65c816s {
             e: true,
             stopped: false,
             waiting: false,
+            nmi_line: false,
+            nmi_pending: false,
+            irq_line: false,
         }
     }
 
-    pub fn reset(&mut self, abus: &mut ABus) {
-        self.pc = abus.page_wrapping_cpu_read16(RESET8);
+    pub fn reset(&mut self, abus: &ABus) {
+        self.pc = abus.page_wrapping_cpu_peek16(RESET8);
+        self.stopped = false;
+        self.waiting = false;
+        self.nmi_pending = false;
+    }
+
+    /// Sets the state of the NMI input
+    ///
+    /// NMI is edge-triggered so it is only raised when the input goes from released to asserted.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    /// Sets the state of the IRQ input
+    ///
+    /// IRQ is level-triggered so it is raised for as long as the input is asserted and `I` is
+    /// clear.
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
     }
 
     /// Returns the value of `C`
@@ -174,6 +204,26 @@ impl W65c816s {
     ///
     /// `abus` is used for memory addressing as needed
     pub fn step(&mut self, abus: &mut ABus) -> u8 {
+        if self.stopped {
+            // Only reset gets us out of STP
+            return 1;
+        }
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.waiting = false;
+            return self.interrupt(NMI8, NMI16, abus);
+        }
+        if self.irq_line {
+            // IRQ releases WAI even when it is disabled by `I`
+            self.waiting = false;
+            if !self.p.i {
+                return self.interrupt(IRQBRK8, IRQ16, abus);
+            }
+        }
+        if self.waiting {
+            return 1;
+        }
+
         let addr = self.current_address();
         let opcode = abus.cpu_read8(addr);
 
@@ -758,10 +808,12 @@ impl W65c816s {
             }
             op::STP => {
                 self.stopped = true;
+                self.pc = self.pc.wrapping_add(1);
                 3
             }
             op::WAI => {
                 self.waiting = true;
+                self.pc = self.pc.wrapping_add(1);
                 3
             }
             op::TAX => transfer!(self.p.x, self.a, self.x),
@@ -817,6 +869,38 @@ impl W65c816s {
         }
     }
 
+    /// Enters the interrupt handler pointed by `vector8` in emulation mode or `vector16` in native
+    /// mode and returns the number of cycles it took
+    ///
+    /// `PB` is only pushed in native mode and the B(RK)-flag is pushed as cleared in emulation
+    /// mode to distinguish the interrupt from BRK.
+    fn interrupt(&mut self, vector8: u32, vector16: u32, abus: &mut ABus) -> u8 {
+        let vector = if self.e {
+            let pc = self.pc;
+            self.push16(pc, abus);
+            let p = self.p.value();
+            self.push8(p & !P_X, abus);
+            vector8
+        } else {
+            let pb = self.pb;
+            self.push8(pb, abus);
+            let pc = self.pc;
+            self.push16(pc, abus);
+            let p = self.p.value();
+            self.push8(p, abus);
+            vector16
+        };
+        self.pb = 0x00;
+        self.pc = abus.page_wrapping_cpu_read16(vector);
+        self.p.i = true;
+        self.p.d = false;
+        if self.e {
+            7
+        } else {
+            8
+        }
+    }
+
     // Addressing modes
     // TODO: DRY, mismatch funcs and macros?
 
@@ -840,7 +924,9 @@ impl W65c816s {
     ///
     /// Data is at lo`[$DBHHLL+X]` hi`[$DBHHLL+X+1]`
     pub fn abs_x(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
-        self.abs_n_common(abus.fetch_operand16(addr), self.x)
+        let db_addr = abus.fetch_operand16(addr);
+        self.index_cycle(db_addr, self.x, abus);
+        self.abs_n_common(db_addr, self.x)
     }
     pub fn peek_abs_x(&self, addr: u32, abus: &ABus) -> (u32, WrappingMode) {
         self.abs_n_common(abus.peek_operand16(addr), self.x)
@@ -851,12 +937,24 @@ impl W65c816s {
     ///
     /// Data is at lo`[$DBHHLL+Y]` hi`[$DBHHLL+Y+1]`
     pub fn abs_y(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
-        self.abs_n_common(abus.fetch_operand16(addr), self.y)
+        let db_addr = abus.fetch_operand16(addr);
+        self.index_cycle(db_addr, self.y, abus);
+        self.abs_n_common(db_addr, self.y)
     }
     pub fn peek_abs_y(&self, addr: u32, abus: &ABus) -> (u32, WrappingMode) {
         self.abs_n_common(abus.peek_operand16(addr), self.y)
     }
 
+    /// Spends the internal cycle indexed modes take to add `index` to `base`
+    ///
+    /// Reads with 8bit index registers skip it unless a page boundary is crossed. Writes always
+    /// take it, which is accounted for at the end of the instruction.
+    fn index_cycle(&self, base: u16, index: u16, abus: &mut ABus) {
+        if !self.p.x || (base & 0xFF00) != (base.wrapping_add(index) & 0xFF00) {
+            abus.cpu_idle();
+        }
+    }
+
     fn abs_n_common(&self, db_addr: u16, n_reg: u16) -> (u32, WrappingMode) {
         (
             (addr_8_16(self.db, db_addr) + n_reg as u32) & 0x00FFFFFF,
@@ -903,6 +1001,8 @@ impl W65c816s {
     /// 16bit pointer at lo`[$00][$HHLL+X]` hi`[$00][$HHLL+X+1]` with actual data at `[$PBhilo]`
     pub fn abs_x_ptr16(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
         let pointer = addr_8_16(self.pb, abus.fetch_operand16(addr).wrapping_add(self.x));
+        // Index is added in an internal cycle
+        abus.cpu_idle();
         (
             addr_8_16(self.pb, abus.bank_wrapping_cpu_read16(pointer)),
             WrappingMode::Bank,
@@ -922,11 +1022,20 @@ impl W65c816s {
     /// Data at `[$00][$DL][$LL]` for "old" instructions if in emulation mode and `DL` is `$00`,
     /// otherwise lo`[$00][$D+LL]` hi`[$00][$D+LL+1]`. Math turns out to be the same for both.
     pub fn dir(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
-        self.dir_common(abus.fetch_operand8(addr) as u16)
+        let offset = abus.fetch_operand8(addr);
+        self.dl_cycle(abus);
+        self.dir_common(offset as u16)
     }
     pub fn peek_dir(&self, addr: u32, abus: &ABus) -> (u32, WrappingMode) {
         self.dir_common(abus.peek_operand8(addr) as u16)
     }
+    /// Spends the internal cycle direct page modes take when `DL` isn't `$00`
+    fn dl_cycle(&self, abus: &mut ABus) {
+        if self.d & 0xFF != 0 {
+            abus.cpu_idle();
+        }
+    }
+
     pub fn dir_common(&self, offset: u16) -> (u32, WrappingMode) {
         (
             self.d.wrapping_add(offset as u16) as u32,
@@ -941,16 +1050,18 @@ impl W65c816s {
     /// otherwise hi`[$00][$D+LL+X+1]`.
     pub fn dir_x(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
         // TODO: Combine with dir_y
+        let offset = abus.fetch_operand8(addr);
+        self.dl_cycle(abus);
+        // Index is added in an internal cycle
+        abus.cpu_idle();
         if self.e && (self.d & 0xFF) == 0 {
             (
-                (self.d | (abus.fetch_operand8(addr).wrapping_add(self.x as u8) as u16)) as u32,
+                (self.d | (offset.wrapping_add(self.x as u8) as u16)) as u32,
                 WrappingMode::Page,
             )
         } else {
             (
-                self.d
-                    .wrapping_add(abus.fetch_operand8(addr) as u16)
-                    .wrapping_add(self.x) as u32,
+                self.d.wrapping_add(offset as u16).wrapping_add(self.x) as u32,
                 WrappingMode::Bank,
             )
         }
@@ -980,16 +1091,18 @@ impl W65c816s {
     /// otherwise hi`[$00][$D+LL+Y+1]`
     pub fn dir_y(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
         // TODO: Combine with dir_x
+        let offset = abus.fetch_operand8(addr);
+        self.dl_cycle(abus);
+        // Index is added in an internal cycle
+        abus.cpu_idle();
         if self.e && (self.d & 0xFF) == 0 {
             (
-                (self.d | (abus.fetch_operand8(addr).wrapping_add(self.y as u8) as u16)) as u32,
+                (self.d | (offset.wrapping_add(self.y as u8) as u16)) as u32,
                 WrappingMode::Page,
             )
         } else {
             (
-                self.d
-                    .wrapping_add(abus.fetch_operand8(addr) as u16)
-                    .wrapping_add(self.y) as u32,
+                self.d.wrapping_add(offset as u16).wrapping_add(self.y) as u32,
                 WrappingMode::Bank,
             )
         }
@@ -1104,7 +1217,9 @@ impl W65c816s {
     /// `DL` is `$00`, otherwise lo`[$00][$D+LL]` hi`[$00][$D+LL+1]`. Data at lo`[$DBhilo+Y]`
     /// hi`[$DBhilo+Y+1]`.
     pub fn dir_ptr16_y(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
-        self.dir_ptr16_y_common(self.dir_ptr16(addr, abus).0)
+        let pointer = self.dir_ptr16(addr, abus).0;
+        self.index_cycle(pointer as u16, self.y, abus);
+        self.dir_ptr16_y_common(pointer)
     }
     pub fn peek_dir_ptr16_y(&self, addr: u32, abus: &ABus) -> (u32, WrappingMode) {
         self.dir_ptr16_y_common(self.peek_dir_ptr16(addr, abus).0)
@@ -1228,7 +1343,10 @@ impl W65c816s {
     ///
     /// Data at lo`[$00][$LL+S]` hi`[$00][$LL+S+1]`
     pub fn stack(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
-        self.stack_common(abus.fetch_operand8(addr) as u16)
+        let offset = abus.fetch_operand8(addr);
+        // S is added in an internal cycle
+        abus.cpu_idle();
+        self.stack_common(offset as u16)
     }
     pub fn peek_stack(&self, addr: u32, abus: &ABus) -> (u32, WrappingMode) {
         self.stack_common(abus.peek_operand8(addr) as u16)
@@ -1244,9 +1362,13 @@ impl W65c816s {
     /// hi`[$DBhilo+Y+1]`.
     pub fn stack_ptr16_y(&self, addr: u32, abus: &mut ABus) -> (u32, WrappingMode) {
         let pointer = self.s.wrapping_add(abus.fetch_operand8(addr) as u16) as u32;
+        // S is added in an internal cycle
+        abus.cpu_idle();
+        let data_addr = addr_8_16(self.db, abus.bank_wrapping_cpu_read16(pointer));
+        // As is Y
+        abus.cpu_idle();
         (
-            (addr_8_16(self.db, abus.bank_wrapping_cpu_read16(pointer)) + self.y as u32)
-                & 0x00FFFFFF,
+            (data_addr + self.y as u32) & 0x00FFFFFF,
             WrappingMode::AddrSpace,
         )
     }
@@ -1696,6 +1818,8 @@ impl W65c816s {
             let data = abus.cpu_read8(data_addr.0).wrapping_sub(1);
             self.p.n = data > 0x7F;
             self.p.z = data == 0;
+            // Modify cycle
+            abus.cpu_idle();
             abus.cpu_write8(data_addr.0, data);
         } else {
             // 16-bit accumulator
@@ -1703,10 +1827,14 @@ impl W65c816s {
             match data_addr.1 {
                 WrappingMode::Bank => {
                     data = abus.bank_wrapping_cpu_read16(data_addr.0).wrapping_sub(1);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.bank_wrapping_cpu_write16(data_addr.0, data);
                 }
                 WrappingMode::AddrSpace => {
                     data = abus.addr_wrapping_cpu_read16(data_addr.0).wrapping_sub(1);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.addr_wrapping_cpu_write16(data_addr.0, data);
                 }
                 WrappingMode::Page => unreachable!(),
@@ -1726,6 +1854,8 @@ impl W65c816s {
             let data = abus.cpu_read8(data_addr.0).wrapping_add(1);
             self.p.n = data > 0x7F;
             self.p.z = data == 0;
+            // Modify cycle
+            abus.cpu_idle();
             abus.cpu_write8(data_addr.0, data);
         } else {
             // 16-bit accumulator
@@ -1733,10 +1863,14 @@ impl W65c816s {
             match data_addr.1 {
                 WrappingMode::Bank => {
                     data = abus.bank_wrapping_cpu_read16(data_addr.0).wrapping_add(1);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.bank_wrapping_cpu_write16(data_addr.0, data);
                 }
                 WrappingMode::AddrSpace => {
                     data = abus.addr_wrapping_cpu_read16(data_addr.0).wrapping_add(1);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.addr_wrapping_cpu_write16(data_addr.0, data);
                 }
                 WrappingMode::Page => unreachable!(),
@@ -1853,6 +1987,8 @@ impl W65c816s {
         if self.p.m {
             // 8-bit accumulator
             let data = abus.cpu_read8(data_addr.0);
+            // Modify cycle
+            abus.cpu_idle();
             abus.cpu_write8(data_addr.0, data & !(self.a as u8));
             self.p.z = self.a as u8 & data == 0;
         } else {
@@ -1861,10 +1997,14 @@ impl W65c816s {
             match data_addr.1 {
                 WrappingMode::Bank => {
                     data = abus.bank_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.bank_wrapping_cpu_write16(data_addr.0, data & !self.a)
                 }
                 WrappingMode::AddrSpace => {
                     data = abus.addr_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.addr_wrapping_cpu_write16(data_addr.0, data & !self.a)
                 }
                 WrappingMode::Page => unreachable!(),
@@ -1881,6 +2021,8 @@ impl W65c816s {
         if self.p.m {
             // 8-bit accumulator
             let data = abus.cpu_read8(data_addr.0);
+            // Modify cycle
+            abus.cpu_idle();
             abus.cpu_write8(data_addr.0, data | (self.a as u8));
             self.p.z = self.a as u8 & data == 0;
         } else {
@@ -1889,10 +2031,14 @@ impl W65c816s {
             match data_addr.1 {
                 WrappingMode::Bank => {
                     data = abus.bank_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.bank_wrapping_cpu_write16(data_addr.0, data | self.a)
                 }
                 WrappingMode::AddrSpace => {
                     data = abus.addr_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.addr_wrapping_cpu_write16(data_addr.0, data | self.a)
                 }
                 WrappingMode::Page => unreachable!(),
@@ -1912,16 +2058,22 @@ impl W65c816s {
         if self.p.m {
             // 8-bit accumulator
             let data = abus.cpu_read8(data_addr.0);
+            // Modify cycle
+            abus.cpu_idle();
             abus.cpu_write8(data_addr.0, self.arithmetic_shift_left8(data));
         } else {
             // 16-bit accumulator
             match data_addr.1 {
                 WrappingMode::Bank => {
                     let data = abus.bank_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.bank_wrapping_cpu_write16(data_addr.0, self.arithmetic_shift_left16(data))
                 }
                 WrappingMode::AddrSpace => {
                     let data = abus.addr_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.addr_wrapping_cpu_write16(data_addr.0, self.arithmetic_shift_left16(data))
                 }
                 WrappingMode::Page => unreachable!(),
@@ -1940,16 +2092,22 @@ impl W65c816s {
         if self.p.m {
             // 8-bit accumulator
             let data = abus.cpu_read8(data_addr.0);
+            // Modify cycle
+            abus.cpu_idle();
             abus.cpu_write8(data_addr.0, self.logical_shift_right8(data));
         } else {
             // 16-bit accumulator
             match data_addr.1 {
                 WrappingMode::Bank => {
                     let data = abus.bank_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.bank_wrapping_cpu_write16(data_addr.0, self.logical_shift_right16(data))
                 }
                 WrappingMode::AddrSpace => {
                     let data = abus.addr_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.addr_wrapping_cpu_write16(data_addr.0, self.logical_shift_right16(data))
                 }
                 WrappingMode::Page => unreachable!(),
@@ -1968,16 +2126,22 @@ impl W65c816s {
         if self.p.m {
             // 8-bit accumulator
             let data = abus.cpu_read8(data_addr.0);
+            // Modify cycle
+            abus.cpu_idle();
             abus.cpu_write8(data_addr.0, self.rotate_left8(data));
         } else {
             // 16-bit accumulator
             match data_addr.1 {
                 WrappingMode::Bank => {
                     let data = abus.bank_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.bank_wrapping_cpu_write16(data_addr.0, self.rotate_left16(data))
                 }
                 WrappingMode::AddrSpace => {
                     let data = abus.addr_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.addr_wrapping_cpu_write16(data_addr.0, self.rotate_left16(data))
                 }
                 WrappingMode::Page => unreachable!(),
@@ -1996,16 +2160,22 @@ impl W65c816s {
         if self.p.m {
             // 8-bit accumulator
             let data = abus.cpu_read8(data_addr.0);
+            // Modify cycle
+            abus.cpu_idle();
             abus.cpu_write8(data_addr.0, self.rotate_right8(data));
         } else {
             // 16-bit accumulator
             match data_addr.1 {
                 WrappingMode::Bank => {
                     let data = abus.bank_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.bank_wrapping_cpu_write16(data_addr.0, self.rotate_right16(data))
                 }
                 WrappingMode::AddrSpace => {
                     let data = abus.addr_wrapping_cpu_read16(data_addr.0);
+                    // Modify cycle
+                    abus.cpu_idle();
                     abus.addr_wrapping_cpu_write16(data_addr.0, self.rotate_right16(data))
                 }
                 WrappingMode::Page => unreachable!(),
@@ -2251,10 +2421,8 @@ const BRK16: u32 = 0x00FFE6;
 #[allow(dead_code)]
 const ABORT16: u32 = 0x00FFE8;
 /// Native mode non-maskable interrupt vector. Called on vblank
-#[allow(dead_code)]
 const NMI16: u32 = 0x00FFEA;
 /// Native mode interrupt request
-#[allow(dead_code)]
 const IRQ16: u32 = 0x00FFEE;
 /// Emulation mode co-processor vector (unused in SNES?)
 const COP8: u32 = 0x00FFF4;
@@ -2262,7 +2430,6 @@ const COP8: u32 = 0x00FFF4;
 #[allow(dead_code)]
 const ABORT8: u32 = 0x00FFF8;
 /// Emulation mode non-maskable interrupt vector. Called on vblank
-#[allow(dead_code)]
 const NMI8: u32 = 0x00FFFA;
 /// Reset vector, execution begins from this
 const RESET8: u32 = 0x00FFFC;
@@ -1,3 +1,4 @@
+use crate::mmap;
 use log::error;
 
 pub struct Dma {
@@ -15,6 +16,10 @@ pub struct Dma {
     a2a_h: [u8; 8],
     ntr_l: [u8; 8],
     unused: [u8; 8],
+    /// `true` if HDMA on the channel transfers on the current line
+    hdma_do_transfer: [bool; 8],
+    /// `true` if HDMA on the channel has reached the end of its table for this frame
+    hdma_completed: [bool; 8],
 }
 
 impl Dma {
@@ -34,19 +39,72 @@ impl Dma {
             a2a_h: [0; 8],
             ntr_l: [0; 8],
             unused: [0; 8],
+            hdma_do_transfer: [false; 8],
+            hdma_completed: [false; 8],
         }
     }
 
-    #[allow(dead_code)]
     pub fn read_mdma_en(&self) -> u8 {
         self.mdma_en
     }
 
     pub fn write_mdma_en(&mut self, value: u8) {
-        self.mdma_en = value; // TODO: Do transfer
+        self.mdma_en = value;
+    }
+
+    /// Clears the GP-DMA enable bit of `channel` after its transfer has completed
+    pub fn finish_gp(&mut self, channel: usize) {
+        self.mdma_en &= !(1 << channel);
+    }
+
+    /// Returns `true` if `channel` transfers from B-bus to A-bus
+    pub fn b_to_a(&self, channel: usize) -> bool {
+        self.dma_p[channel] & DMAP_B_TO_A != 0
+    }
+
+    /// Returns the current A-bus address of `channel`
+    pub fn a_addr(&self, channel: usize) -> u32 {
+        ((self.a1_b[channel] as u32) << 16)
+            | ((self.a1t_h[channel] as u32) << 8)
+            | (self.a1t_l[channel] as u32)
+    }
+
+    /// Returns the number of bytes in a single transfer unit of `channel`
+    pub fn unit_len(&self, channel: usize) -> usize {
+        TRANSFER_PATTERNS[(self.dma_p[channel] & DMAP_PATTERN) as usize].len()
+    }
+
+    /// Returns the B-bus address of byte `index` of a transfer on `channel`
+    ///
+    /// The address is offset from BBADx based on the transfer pattern in DMAPx.
+    pub fn b_addr(&self, channel: usize, index: usize) -> usize {
+        let pattern = TRANSFER_PATTERNS[(self.dma_p[channel] & DMAP_PATTERN) as usize];
+        let offset = pattern[index % pattern.len()];
+        mmap::BBUS_FIRST | (self.bb_ad[channel].wrapping_add(offset) as usize)
+    }
+
+    /// Steps the A-bus address of `channel` as set in DMAPx and decrements its byte count
+    ///
+    /// The address wraps within the bank. Returns `true` if the transfer is complete. A byte
+    /// count of zero transfers $10000 bytes.
+    pub fn advance_gp(&mut self, channel: usize) -> bool {
+        let a_addr = ((self.a1t_h[channel] as u16) << 8) | (self.a1t_l[channel] as u16);
+        let a_addr = match (self.dma_p[channel] & DMAP_A_STEP) >> 3 {
+            0b00 => a_addr.wrapping_add(1),
+            0b10 => a_addr.wrapping_sub(1),
+            _ => a_addr, // Fixed
+        };
+        self.a1t_l[channel] = a_addr as u8;
+        self.a1t_h[channel] = (a_addr >> 8) as u8;
+
+        let count = ((self.das_h[channel] as u16) << 8) | (self.das_l[channel] as u16);
+        let count = count.wrapping_sub(1);
+        self.das_l[channel] = count as u8;
+        self.das_h[channel] = (count >> 8) as u8;
+
+        count == 0
     }
 
-    #[allow(dead_code)]
     pub fn read_hdma_en(&self) -> u8 {
         self.hdma_en
     }
@@ -55,6 +113,94 @@ impl Dma {
         self.hdma_en = value;
     }
 
+    /// Returns the channels that have HDMA enabled and haven't completed their tables
+    pub fn hdma_active(&self) -> u8 {
+        (0..8)
+            .filter(|&c| !self.hdma_completed[c])
+            .fold(0, |active, c| active | (1 << c))
+            & self.hdma_en
+    }
+
+    /// Returns `true` if HDMA on `channel` uses indirect addressing
+    pub fn hdma_indirect(&self, channel: usize) -> bool {
+        self.dma_p[channel] & DMAP_HDMA_INDIRECT != 0
+    }
+
+    /// Returns `true` if HDMA on `channel` transfers on the current line
+    pub fn hdma_do_transfer(&self, channel: usize) -> bool {
+        self.hdma_do_transfer[channel]
+    }
+
+    /// Resets the HDMA table address of `channel` to the start of the table
+    pub fn start_hdma(&mut self, channel: usize) {
+        self.a2a_l[channel] = self.a1t_l[channel];
+        self.a2a_h[channel] = self.a1t_h[channel];
+        self.hdma_completed[channel] = false;
+    }
+
+    /// Returns the address of the next byte in the HDMA table of `channel`
+    pub fn hdma_table_addr(&self, channel: usize) -> u32 {
+        ((self.a1_b[channel] as u32) << 16)
+            | ((self.a2a_h[channel] as u32) << 8)
+            | (self.a2a_l[channel] as u32)
+    }
+
+    /// Moves the HDMA table address of `channel` to the next byte, wrapping within the bank
+    pub fn advance_hdma_table(&mut self, channel: usize) {
+        let addr = ((self.a2a_h[channel] as u16) << 8) | (self.a2a_l[channel] as u16);
+        let addr = addr.wrapping_add(1);
+        self.a2a_l[channel] = addr as u8;
+        self.a2a_h[channel] = (addr >> 8) as u8;
+    }
+
+    /// Sets the line counter of `channel` from a newly loaded table entry
+    ///
+    /// A zero entry terminates HDMA on the channel for the rest of the frame.
+    pub fn set_hdma_line_counter(&mut self, channel: usize, value: u8) {
+        self.ntr_l[channel] = value;
+        self.hdma_completed[channel] = value == 0;
+        self.hdma_do_transfer[channel] = value != 0;
+    }
+
+    /// Sets the indirect data address of `channel` from a newly loaded table entry
+    pub fn set_hdma_indirect_addr(&mut self, channel: usize, low: u8, high: u8) {
+        self.das_l[channel] = low;
+        self.das_h[channel] = high;
+    }
+
+    /// Returns the address of the next byte of HDMA data on `channel`
+    pub fn hdma_data_addr(&self, channel: usize) -> u32 {
+        if self.hdma_indirect(channel) {
+            ((self.das_b[channel] as u32) << 16)
+                | ((self.das_h[channel] as u32) << 8)
+                | (self.das_l[channel] as u32)
+        } else {
+            self.hdma_table_addr(channel)
+        }
+    }
+
+    /// Moves the HDMA data address of `channel` to the next byte
+    pub fn advance_hdma_data(&mut self, channel: usize) {
+        if self.hdma_indirect(channel) {
+            let addr = ((self.das_h[channel] as u16) << 8) | (self.das_l[channel] as u16);
+            let addr = addr.wrapping_add(1);
+            self.das_l[channel] = addr as u8;
+            self.das_h[channel] = (addr >> 8) as u8;
+        } else {
+            self.advance_hdma_table(channel);
+        }
+    }
+
+    /// Decrements the line counter of `channel` after a line
+    ///
+    /// Returns `true` if the current entry is exhausted and the next one should be loaded.
+    pub fn step_hdma_line_counter(&mut self, channel: usize) -> bool {
+        self.ntr_l[channel] = self.ntr_l[channel].wrapping_sub(1);
+        // Repeat flag makes the entry transfer on every line instead of just the first one
+        self.hdma_do_transfer[channel] = self.ntr_l[channel] & NTRL_REPEAT != 0;
+        self.ntr_l[channel] & NTRL_LINES == 0
+    }
+
     pub fn read(&self, addr: usize) -> u8 {
         let port = addr & 0x000F;
         let channel = (addr & 0x00F0) >> 4;
@@ -101,6 +247,34 @@ impl Dma {
     }
 }
 
+// DMAPx bits
+/// Transfer direction, set for B-bus to A-bus
+const DMAP_B_TO_A: u8 = 0b1000_0000;
+/// HDMA addressing mode, set for indirect
+const DMAP_HDMA_INDIRECT: u8 = 0b0100_0000;
+/// A-bus address step, 0 for increment, 2 for decrement and 1 or 3 for fixed
+const DMAP_A_STEP: u8 = 0b0001_1000;
+/// Transfer unit pattern
+const DMAP_PATTERN: u8 = 0b0000_0111;
+
+// NTRLx bits
+/// HDMA repeat flag
+const NTRL_REPEAT: u8 = 0b1000_0000;
+/// HDMA line count
+const NTRL_LINES: u8 = 0b0111_1111;
+
+/// B-bus address offsets of the transfer units, indexed by the pattern in DMAPx
+const TRANSFER_PATTERNS: [&[u8]; 8] = [
+    &[0],
+    &[0, 1],
+    &[0, 0],
+    &[0, 0, 1, 1],
+    &[0, 1, 2, 3],
+    &[0, 1, 0, 1],
+    &[0, 0],
+    &[0, 0, 1, 1],
+];
+
 // Port types
 const DMAPX: usize = 0x0000;
 const BBADX: usize = 0x0001;
@@ -115,3 +289,78 @@ const A2AXH: usize = 0x0009;
 const NTRLX: usize = 0x000A;
 const UNUSEDX: usize = 0x000B;
 const MIRRX: usize = 0x000F;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a `Dma` with `channel` set up through its registers
+    fn setup(channel: usize, dma_p: u8, a_addr: u16, count: u16) -> Dma {
+        let mut dma = Dma::new();
+        let base = mmap::DMA_FIRST | (channel << 4);
+        dma.write(base | DMAPX, dma_p);
+        dma.write(base | BBADX, 0x18);
+        dma.write(base | A1TXL, a_addr as u8);
+        dma.write(base | A1TXH, (a_addr >> 8) as u8);
+        dma.write(base | A1BX, 0x7E);
+        dma.write(base | DASXL, count as u8);
+        dma.write(base | DASXH, (count >> 8) as u8);
+        dma
+    }
+
+    #[test]
+    fn transfer_patterns() {
+        let expected: [&[usize]; 8] = [
+            &[0x2118],
+            &[0x2118, 0x2119],
+            &[0x2118, 0x2118],
+            &[0x2118, 0x2118, 0x2119, 0x2119],
+            &[0x2118, 0x2119, 0x211A, 0x211B],
+            &[0x2118, 0x2119, 0x2118, 0x2119],
+            &[0x2118, 0x2118],
+            &[0x2118, 0x2118, 0x2119, 0x2119],
+        ];
+        for (pattern, addrs) in expected.iter().enumerate() {
+            let dma = setup(3, pattern as u8, 0, 1);
+            assert_eq!(dma.unit_len(3), addrs.len(), "pattern {}", pattern);
+            for index in 0..8 {
+                assert_eq!(
+                    dma.b_addr(3, index),
+                    addrs[index % addrs.len()],
+                    "pattern {} byte {}",
+                    pattern,
+                    index
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn zero_count_transfers_64k() {
+        let mut dma = setup(0, 0x00, 0, 0);
+        for _ in 0..0xFFFF {
+            assert!(!dma.advance_gp(0));
+        }
+        assert!(dma.advance_gp(0));
+    }
+
+    #[test]
+    fn a_addr_steps() {
+        // Increment wraps within the bank
+        let mut dma = setup(0, 0x00, 0xFFFF, 2);
+        dma.advance_gp(0);
+        assert_eq!(dma.a_addr(0), 0x7E0000);
+
+        for fixed in [0x08, 0x18] {
+            let mut dma = setup(1, fixed, 0x1234, 2);
+            dma.advance_gp(1);
+            assert_eq!(dma.a_addr(1), 0x7E1234);
+        }
+
+        let mut dma = setup(2, 0x10, 0x0000, 3);
+        dma.advance_gp(2);
+        assert_eq!(dma.a_addr(2), 0x7EFFFF);
+        dma.advance_gp(2);
+        assert_eq!(dma.a_addr(2), 0x7EFFFE);
+    }
+}
@@ -39,6 +39,9 @@ impl JoyIo {
     pub fn joy_b(&self) -> u8 {
         self.joy_b
     }
+    pub fn wr_io(&self) -> u8 {
+        self.wr_io
+    }
     pub fn rd_io(&self) -> u8 {
         self.rd_io
     }
@@ -8,7 +8,8 @@ mod joypad;
 pub mod mmap;
 mod mpydiv;
 mod oam;
+pub mod ppu;
 mod ppu_io;
-mod rom;
+pub mod rom;
 pub mod snes;
 mod vram;
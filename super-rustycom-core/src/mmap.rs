@@ -20,6 +20,10 @@ pub const SYS_LAST: usize = 0x7FFF;
 pub const LOROM_FIRST: usize = 0x8000;
 pub const LOROM_LAST: usize = 0xFFFF;
 
+// B-bus as seen from the system area
+pub const BBUS_FIRST: usize = 0x2100;
+pub const BBUS_LAST: usize = 0x21FF;
+
 // System area map
 pub const WRAM_MIRR_FIRST: usize = 0x0000;
 pub const WRAM_MIRR_LAST: usize = 0x1FFF;
@@ -31,6 +35,8 @@ pub const WMDATA: usize = 0x2180; // R/W
 pub const WMADDL: usize = 0x2181; // W
 pub const WMADDM: usize = 0x2182; // W
 pub const WMADDH: usize = 0x2183; // W
+pub const OLD_JOY_FIRST: usize = 0x4000;
+pub const OLD_JOY_LAST: usize = 0x41FF;
 pub const JOYWR: usize = 0x4016; // CPU W
 pub const JOYA: usize = 0x4016; // CPU R
 pub const JOYB: usize = 0x4017; // CPU R
@@ -17,6 +17,24 @@ impl Oam {
         &self.mem
     }
 
+    /// Returns the index of the sprite with the highest priority
+    ///
+    /// Priority rotation makes the sprite at the reload address the first one, otherwise it is
+    /// sprite 0.
+    pub fn first_sprite(&self) -> usize {
+        if self.priority != 0 {
+            ((self.reload >> 1) & 0x7F) as usize
+        } else {
+            0
+        }
+    }
+
+    /// Resets the address to the value last written to OAMADDL/OAMADDH
+    pub fn reload_addr(&mut self) {
+        self.addr = self.reload;
+        self.odd_access = false;
+    }
+
     pub fn write_oamddl(&mut self, addr: u8) {
         self.reload = self.reload & 0xFF00 | (addr as u16);
         self.addr = self.reload;
@@ -49,6 +67,14 @@ impl Oam {
         self.increment();
     }
 
+    /// Writes `value` to byte `addr` instead of the current address, which is still incremented
+    ///
+    /// This is where writes land during active display, as the PPU has taken over the address.
+    pub fn write_data_at(&mut self, addr: usize, value: u8) {
+        self.mem[addr % OAM_SIZE] = value;
+        self.increment();
+    }
+
     pub fn read_data(&mut self) -> u8 {
         let value = self.peek_data();
         self.increment();
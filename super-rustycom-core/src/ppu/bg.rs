@@ -0,0 +1,304 @@
+use super::{cgram_color, is_hires, layer_z, vram_word, Layer, Pixel, BGMODE_MODE};
+use crate::ppu_io::{DoubleReg, PpuIo};
+
+/// Tiled background layer as set up in the PPU registers
+pub struct Background {
+    tilemap: Tilemap,
+    /// Word address of the tile data
+    char_base: u16,
+    /// Bits per pixel
+    bpp: u8,
+    /// Offset of the first palette in CGRAM
+    palette_base: u8,
+    /// `true` if the layer is rendered at 512 pixels per line
+    hires: bool,
+    hofs: u16,
+    vofs: u16,
+    /// Bit set in offset-per-tile entries that apply to the layer, 0 if the mode has none
+    offset_valid: u16,
+    /// Depths for tilemap priorities 0 and 1
+    z: [u8; 2],
+}
+
+impl Background {
+    /// Gathers the settings of `layer` from `io`
+    ///
+    /// Returns `None` if the layer isn't shown in the current BG mode.
+    pub fn new(layer: Layer, io: &PpuIo) -> Option<Background> {
+        let mode = io.bg_mode & BGMODE_MODE;
+        let index = layer_index(layer);
+        let bpp = match (mode, index) {
+            (0, _) => 2,
+            (1, 0) | (1, 1) => 4,
+            (1, 2) => 2,
+            (2, 0) | (2, 1) => 4,
+            (3, 0) => 8,
+            (3, 1) => 4,
+            (4, 0) => 8,
+            (4, 1) => 2,
+            (5, 0) => 4,
+            (5, 1) => 2,
+            (6, 0) => 4,
+            _ => return None,
+        };
+        // Mode 0 has a separate set of 2bpp palettes for each BG
+        let palette_base = if mode == 0 { index * 32 } else { 0 };
+        let offset_valid = match mode {
+            2 | 4 | 6 => OPT_BG1_VALID << index,
+            _ => 0,
+        };
+        let nba = match layer {
+            Layer::Bg1 => io.bg12_nba,
+            Layer::Bg2 => io.bg12_nba >> 4,
+            Layer::Bg3 => io.bg34_nba,
+            Layer::Bg4 => io.bg34_nba >> 4,
+            Layer::Obj => unreachable!(),
+        };
+        let (hofs, vofs) = scroll_regs(layer, io);
+
+        Some(Background {
+            tilemap: Tilemap::new(layer, io),
+            char_base: ((nba & 0x0F) as u16) << 12,
+            bpp,
+            palette_base,
+            hires: is_hires(io),
+            hofs: hofs.value() & 0x3FF,
+            vofs: vofs.value() & 0x3FF,
+            offset_valid,
+            z: [layer_z(layer, 0, io), layer_z(layer, 1, io)],
+        })
+    }
+
+    /// Renders scanline `y` of the layer into `line`
+    ///
+    /// `line` is expected to be 512 pixels wide in hi-res modes and 256 otherwise.
+    pub fn render_line(&self, y: u16, io: &PpuIo, vram: &[u8], cgram: &[u8], line: &mut [Pixel]) {
+        // Scrolling moves in full pixels even when the line is hi-res
+        let hofs = if self.hires {
+            self.hofs << 1
+        } else {
+            self.hofs
+        };
+        // Offset-per-tile table is read from BG3 tilemap
+        let offsets = if self.offset_valid != 0 {
+            Some(OffsetTable::new(io))
+        } else {
+            None
+        };
+
+        for (x, px) in line.iter_mut().enumerate() {
+            let x = x as u16;
+            let mut map_x = x + hofs;
+            let mut map_y = y + self.vofs;
+            if let Some(offsets) = &offsets {
+                // The first, partially shown column doesn't get an offset
+                let column_x = x + (hofs & 0b111);
+                if column_x >= 8 {
+                    let (h_entry, v_entry) = offsets.entries(column_x - 8, vram);
+                    if let Some(entry) = h_entry.filter(|e| e & self.offset_valid != 0) {
+                        map_x = column_x.wrapping_add(entry & !0b111);
+                    }
+                    if let Some(entry) = v_entry.filter(|e| e & self.offset_valid != 0) {
+                        map_y = y.wrapping_add(entry);
+                    }
+                }
+            }
+            map_x &= 0x3FF;
+            map_y &= 0x3FF;
+            let entry = self.tilemap.entry(map_x, map_y, vram);
+
+            // Tilemap entry is vhopppcc cccccccc
+            let mut tile = entry & 0x03FF;
+            let palette = ((entry >> 10) & 0b111) as u8;
+            let priority = ((entry >> 13) & 0b1) as usize;
+            let tile_width = self.tilemap.tile_width();
+            let tile_height = self.tilemap.tile_height();
+            let mut fine_x = map_x % tile_width;
+            let mut fine_y = map_y % tile_height;
+            if entry & 0x4000 != 0 {
+                fine_x = tile_width - 1 - fine_x;
+            }
+            if entry & 0x8000 != 0 {
+                fine_y = tile_height - 1 - fine_y;
+            }
+            // Larger tiles are made of neighbouring tiles in the 16 tile wide character table
+            tile = (tile + (fine_x / 8) + (fine_y / 8) * 16) & 0x03FF;
+
+            let index = tile_pixel(
+                self.char_base,
+                tile,
+                self.bpp,
+                (fine_x % 8) as u8,
+                (fine_y % 8) as u8,
+                vram,
+            );
+            if index != 0 {
+                // 8bpp layers use the whole CGRAM as a single palette
+                let color_index = if self.bpp == 8 {
+                    index
+                } else {
+                    self.palette_base
+                        .wrapping_add(palette << self.bpp)
+                        .wrapping_add(index)
+                };
+                *px = Pixel {
+                    color: cgram_color(cgram, color_index),
+                    cgram_index: color_index,
+                    z: self.z[priority],
+                    color_math: true,
+                };
+            }
+        }
+    }
+}
+
+/// Tilemap of a background layer
+struct Tilemap {
+    /// Word address of the first screen
+    base: u16,
+    /// `true` if the tilemap is two screens wide
+    wide: bool,
+    /// `true` if the tilemap is two screens tall
+    tall: bool,
+    /// `true` if tiles are 16 pixels wide
+    wide_tiles: bool,
+    /// `true` if tiles are 16 pixels tall
+    tall_tiles: bool,
+}
+
+impl Tilemap {
+    fn new(layer: Layer, io: &PpuIo) -> Tilemap {
+        let sc = match layer {
+            Layer::Bg1 => io.bg1_sc,
+            Layer::Bg2 => io.bg2_sc,
+            Layer::Bg3 => io.bg3_sc,
+            Layer::Bg4 => io.bg4_sc,
+            Layer::Obj => unreachable!(),
+        };
+        let large_tiles = io.bg_mode & (BGMODE_BG1_LARGE_TILES << layer_index(layer)) != 0;
+        Tilemap {
+            base: ((sc & 0b1111_1100) as u16) << 8,
+            wide: sc & 0b01 != 0,
+            tall: sc & 0b10 != 0,
+            // Hi-res modes always have 16 pixel wide tiles
+            wide_tiles: large_tiles || is_hires(io),
+            tall_tiles: large_tiles,
+        }
+    }
+
+    fn tile_width(&self) -> u16 {
+        if self.wide_tiles {
+            16
+        } else {
+            8
+        }
+    }
+
+    fn tile_height(&self) -> u16 {
+        if self.tall_tiles {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Returns the entry of the tile at pixel (`x`, `y`) of the full tilemap
+    fn entry(&self, x: u16, y: u16, vram: &[u8]) -> u16 {
+        let tile_x = x / self.tile_width();
+        let tile_y = y / self.tile_height();
+        // Tilemap is made of 32x32 screens laid out left to right, top to bottom
+        let mut offset = ((tile_y & 0x1F) << 5) + (tile_x & 0x1F);
+        if self.wide && (tile_x & 0x20) != 0 {
+            offset += 0x400;
+        }
+        if self.tall && (tile_y & 0x20) != 0 {
+            offset += if self.wide { 0x800 } else { 0x400 };
+        }
+        vram_word(vram, self.base.wrapping_add(offset))
+    }
+}
+
+/// Per-column scroll offsets of modes 2, 4 and 6 stored in BG3 tilemap
+struct OffsetTable {
+    tilemap: Tilemap,
+    hofs: u16,
+    vofs: u16,
+    /// `true` if each column has a single entry that holds either H or V offset
+    single: bool,
+}
+
+impl OffsetTable {
+    fn new(io: &PpuIo) -> OffsetTable {
+        let (hofs, vofs) = scroll_regs(Layer::Bg3, io);
+        OffsetTable {
+            tilemap: Tilemap::new(Layer::Bg3, io),
+            hofs: hofs.value() & 0x3FF,
+            vofs: vofs.value() & 0x3FF,
+            single: io.bg_mode & BGMODE_MODE == 4,
+        }
+    }
+
+    /// Returns the H and V offset entries for the column at `x` pixels from the first offset column
+    fn entries(&self, x: u16, vram: &[u8]) -> (Option<u16>, Option<u16>) {
+        let x = (x + (self.hofs & !0b111)) & 0x3FF;
+        let first = self.tilemap.entry(x, self.vofs, vram);
+        if self.single {
+            if first & OPT_VERTICAL != 0 {
+                (None, Some(first))
+            } else {
+                (Some(first), None)
+            }
+        } else {
+            let second = self.tilemap.entry(x, (self.vofs + 8) & 0x3FF, vram);
+            (Some(first), Some(second))
+        }
+    }
+}
+
+/// Returns the index of `layer` among the BGs
+fn layer_index(layer: Layer) -> u8 {
+    match layer {
+        Layer::Bg1 => 0,
+        Layer::Bg2 => 1,
+        Layer::Bg3 => 2,
+        Layer::Bg4 => 3,
+        Layer::Obj => unreachable!(),
+    }
+}
+
+/// Returns the H and V scroll registers of `layer`
+fn scroll_regs(layer: Layer, io: &PpuIo) -> (&DoubleReg, &DoubleReg) {
+    match layer {
+        Layer::Bg1 => (&io.bg1_hofs, &io.bg1_vofs),
+        Layer::Bg2 => (&io.bg2_hofs, &io.bg2_vofs),
+        Layer::Bg3 => (&io.bg3_hofs, &io.bg3_vofs),
+        Layer::Bg4 => (&io.bg4_hofs, &io.bg4_vofs),
+        Layer::Obj => unreachable!(),
+    }
+}
+
+/// Returns the color index of pixel (`x`, `y`) in `tile` at `bpp` bits per pixel
+///
+/// Tiles are stored as pairs of bitplanes interleaved by row, one row per word with the lower
+/// plane in the low byte.
+pub fn tile_pixel(char_base: u16, tile: u16, bpp: u8, x: u8, y: u8, vram: &[u8]) -> u8 {
+    let tile_addr = char_base.wrapping_add(tile.wrapping_mul(bpp as u16 * 4));
+    let mut index = 0;
+    for pair in 0..(bpp / 2) {
+        let planes = vram_word(vram, tile_addr + (pair as u16) * 8 + y as u16);
+        let low = ((planes >> (7 - x)) & 0b1) as u8;
+        let high = ((planes >> (15 - x)) & 0b1) as u8;
+        index |= (low | (high << 1)) << (pair * 2);
+    }
+    index
+}
+
+// BGMODE bits
+/// BG1 16x16 tiles, BG2-4 are the following bits
+const BGMODE_BG1_LARGE_TILES: u8 = 0b0001_0000;
+
+// Offset-per-tile entry bits
+/// Entry applies to BG1, BG2 is the following bit
+const OPT_BG1_VALID: u16 = 0x2000;
+/// Mode 4 entry is a V offset instead of H
+const OPT_VERTICAL: u16 = 0x8000;
@@ -0,0 +1,623 @@
+mod bg;
+mod mode7;
+mod obj;
+pub mod timing;
+mod window;
+
+use self::bg::Background;
+use self::mode7::Mode7;
+use crate::oam::Oam;
+use crate::ppu_io::PpuIo;
+
+/// Width of the output picture in pixels
+pub const SCREEN_WIDTH: usize = 256;
+/// Width of the output picture in pixels when a hi-res mode is used
+pub const SCREEN_WIDTH_HIRES: usize = 512;
+/// Height of the output picture in pixels when 224 lines are displayed
+pub const SCREEN_HEIGHT: usize = 224;
+/// Height of the output picture in pixels when overscan is on and 239 lines are displayed
+pub const SCREEN_HEIGHT_OVERSCAN: usize = 239;
+
+/// Height of the output picture in pixels when overscan and interlace are on
+pub const SCREEN_HEIGHT_INTERLACE: usize = SCREEN_HEIGHT_OVERSCAN * 2;
+
+/// Output picture as rows of 8bit RGB pixels from top to bottom
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Box<[u8]>,
+}
+
+impl Framebuffer {
+    fn new() -> Framebuffer {
+        Framebuffer {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels: vec![0; SCREEN_WIDTH_HIRES * SCREEN_HEIGHT_INTERLACE * 3].into_boxed_slice(),
+        }
+    }
+
+    /// Returns the width of the picture in pixels
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the picture in pixels
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the RGB values of the picture, `width * height * 3` bytes
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels[..self.width * self.height * 3]
+    }
+}
+
+/// Layers that make up the picture
+#[derive(Clone, Copy, PartialEq)]
+enum Layer {
+    Bg1,
+    Bg2,
+    Bg3,
+    Bg4,
+    Obj,
+}
+
+/// A single pixel of a layer
+#[derive(Clone, Copy)]
+struct Pixel {
+    /// BGR555 color
+    color: u16,
+    /// Index of `color` in CGRAM
+    cgram_index: u8,
+    /// Depth of the pixel among all layers, higher is in front and 0 is transparent
+    z: u8,
+    /// `true` if the pixel is affected by color math when on the main screen
+    color_math: bool,
+}
+
+impl Pixel {
+    const TRANSPARENT: Pixel = Pixel {
+        color: 0,
+        cgram_index: 0,
+        z: 0,
+        color_math: true,
+    };
+}
+
+/// Renders the picture from PPU state one scanline at a time
+pub struct Ppu {
+    /// BGR555 colors of the lines rendered so far in the current frame
+    ///
+    /// Lines are always stored at hi-res width so that hi-res and normal lines can be mixed within
+    /// a frame. Rows are stored for both interlace fields and non-interlaced lines fill both.
+    lines: Box<[u16]>,
+    /// `true` if a hi-res line has been rendered in the current frame
+    hires: bool,
+    /// `true` when rendering the odd field of an interlaced picture
+    field: bool,
+    /// Lines left in the current vertical mosaic block
+    mosaic_counter: u16,
+    /// Line mosaic enabled BGs are rendered from
+    mosaic_y: u16,
+    /// CGRAM indices of the main screen colors on the last rendered line
+    cgram_fetches: [u8; SCREEN_WIDTH],
+    /// Last completed picture
+    framebuffer: Framebuffer,
+}
+
+impl Ppu {
+    /// Returns the last completed picture
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Returns the CGRAM index the PPU fetched for pixel `x` of the last rendered line
+    pub fn cgram_fetch(&self, x: usize) -> u8 {
+        self.cgram_fetches[x.min(SCREEN_WIDTH - 1)]
+    }
+
+    /// Renders visible line `y`, where the first visible line is 1
+    ///
+    /// Sets the sprite overflow flags in `io` if they occur on the line.
+    pub fn render_line(&mut self, y: u16, io: &mut PpuIo, vram: &[u8], oam: &Oam, cgram: &[u8]) {
+        let hires = is_hires(io);
+        let width = if hires {
+            SCREEN_WIDTH_HIRES
+        } else {
+            SCREEN_WIDTH
+        };
+
+        // Mosaic blocks start from the first line and the size is only updated between blocks
+        let mosaic_size = ((io.mosaic >> 4) + 1) as u16;
+        if y == 1 {
+            self.mosaic_counter = mosaic_size;
+            self.mosaic_y = 1;
+        } else {
+            // A stopped counter also starts a block, in case rendering didn't start from the top
+            self.mosaic_counter = self.mosaic_counter.saturating_sub(1);
+            if self.mosaic_counter == 0 {
+                self.mosaic_counter = mosaic_size;
+                self.mosaic_y = y;
+            }
+        }
+
+        if io.ini_disp & INIDISP_FORCED_BLANK != 0 {
+            self.cgram_fetches = [0; SCREEN_WIDTH];
+            self.store_line(y, &[0; SCREEN_WIDTH_HIRES], io);
+            return;
+        }
+
+        // Sprites are evaluated even when they aren't shown so that the flags are updated
+        let mut obj_line = [Pixel::TRANSPARENT; SCREEN_WIDTH_HIRES];
+        let obj_field = if io.setini & SETINI_OBJ_INTERLACE != 0 {
+            Some(self.field)
+        } else {
+            None
+        };
+        io.stat_77 |= obj::render_line(
+            y,
+            obj_field,
+            io,
+            vram,
+            oam,
+            cgram,
+            &mut obj_line[..SCREEN_WIDTH],
+        );
+        if hires {
+            // Sprites are positioned in normal resolution
+            for x in (0..SCREEN_WIDTH).rev() {
+                obj_line[x * 2] = obj_line[x];
+                obj_line[x * 2 + 1] = obj_line[x];
+            }
+        }
+
+        let mut main = [Pixel::TRANSPARENT; SCREEN_WIDTH_HIRES];
+        let mut sub = [Pixel::TRANSPARENT; SCREEN_WIDTH_HIRES];
+        for layer in [Layer::Bg1, Layer::Bg2, Layer::Bg3, Layer::Bg4, Layer::Obj] {
+            let mask = layer_mask(layer);
+            if (io.tm | io.ts) & mask == 0 {
+                continue;
+            }
+            let mosaic = layer != Layer::Obj && io.mosaic & mask != 0;
+            let layer_y = if mosaic { self.mosaic_y } else { y };
+            let mut line = [Pixel::TRANSPARENT; SCREEN_WIDTH_HIRES];
+            if layer == Layer::Obj {
+                line = obj_line;
+            } else if io.bg_mode & BGMODE_MODE == 7 {
+                match Mode7::new(layer, io) {
+                    Some(mode7) => mode7.render_line(layer_y, vram, cgram, &mut line[..width]),
+                    None => continue,
+                }
+            } else {
+                // Interlaced hi-res modes show different lines in each field
+                let layer_y = if hires && io.setini & SETINI_INTERLACE != 0 {
+                    layer_y * 2 + self.field as u16
+                } else {
+                    layer_y
+                };
+                match Background::new(layer, io) {
+                    Some(background) => {
+                        background.render_line(layer_y, io, vram, cgram, &mut line[..width])
+                    }
+                    None => continue,
+                }
+            }
+            if mosaic {
+                // Blocks are measured in normal resolution pixels
+                let block = (mosaic_size as usize) * width / SCREEN_WIDTH;
+                for chunk in line[..width].chunks_mut(block) {
+                    let first = chunk[0];
+                    for px in chunk {
+                        *px = first;
+                    }
+                }
+            }
+            if io.cg_adsub & mask == 0 {
+                for px in &mut line[..width] {
+                    px.color_math = false;
+                }
+            }
+
+            let window = if (io.tmw | io.tsw) & mask != 0 {
+                Some(window::layer_mask(layer, io))
+            } else {
+                None
+            };
+            for (screen, enabled, windowed) in [
+                (&mut main, io.tm & mask != 0, io.tmw & mask != 0),
+                (&mut sub, io.ts & mask != 0, io.tsw & mask != 0),
+            ] {
+                if enabled {
+                    let mut line = line;
+                    if windowed {
+                        clip_to_window(window.as_ref().unwrap(), &mut line[..width]);
+                    }
+                    merge(&mut screen[..width], &line[..width]);
+                }
+            }
+        }
+
+        let backdrop = Pixel {
+            color: cgram_color(cgram, 0),
+            cgram_index: 0,
+            z: 0,
+            color_math: io.cg_adsub & CGADSUB_BACKDROP != 0,
+        };
+        let color_window = window::color_mask(io);
+        let black_area = (io.cg_wsel & CGWSEL_MAIN_BLACK) >> 6;
+        let no_math_area = (io.cg_wsel & CGWSEL_PREVENT_MATH) >> 4;
+        let brightness = (io.ini_disp & INIDISP_BRIGHTNESS) as u16;
+        let pseudo_hires = io.setini & SETINI_PSEUDO_HIRES != 0;
+        let mut out = [0; SCREEN_WIDTH_HIRES];
+        for x in 0..width {
+            let in_window = color_window[x * SCREEN_WIDTH / width];
+            let main_px = if main[x].z > 0 { main[x] } else { backdrop };
+            self.cgram_fetches[x * SCREEN_WIDTH / width] = main_px.cgram_index;
+            // Sub screen backdrop is the fixed color
+            let sub_px = if sub[x].z > 0 { Some(sub[x]) } else { None };
+            let sub_color = sub_px.map_or(io.fixed_color, |px| px.color);
+
+            let black = in_color_area(black_area, in_window);
+            let main_color = if black { 0 } else { main_px.color };
+            let main_color = if main_px.color_math && !in_color_area(no_math_area, in_window) {
+                let (math_color, half) = match sub_px {
+                    Some(px) if io.cg_wsel & CGWSEL_ADD_SUB_SCREEN != 0 => (px.color, true),
+                    // Transparent sub screen is never halved
+                    None if io.cg_wsel & CGWSEL_ADD_SUB_SCREEN != 0 => (io.fixed_color, false),
+                    _ => (io.fixed_color, true),
+                };
+                color_math(
+                    main_color,
+                    math_color,
+                    io.cg_adsub & CGADSUB_SUBTRACT != 0,
+                    half && !black && io.cg_adsub & CGADSUB_HALF != 0,
+                )
+            } else {
+                main_color
+            };
+
+            // Even pixels of hi-res output come straight from the sub screen
+            let main_color = apply_brightness(main_color, brightness);
+            let sub_color = apply_brightness(sub_color, brightness);
+            if hires {
+                out[x] = if x % 2 == 0 { sub_color } else { main_color };
+            } else if pseudo_hires {
+                out[x * 2] = sub_color;
+                out[x * 2 + 1] = main_color;
+            } else {
+                out[x * 2] = main_color;
+                out[x * 2 + 1] = main_color;
+            }
+        }
+        self.store_line(y, &out, io);
+        self.hires |= hires || pseudo_hires;
+    }
+
+    /// Stores visible line `y` of the current frame
+    ///
+    /// Interlaced lines only fill the rows of the current field while others fill both.
+    fn store_line(&mut self, y: u16, line: &[u16; SCREEN_WIDTH_HIRES], io: &PpuIo) {
+        let first_row = ((y - 1) as usize) * 2;
+        let rows = if io.setini & SETINI_INTERLACE != 0 {
+            let row = first_row + self.field as usize;
+            row..row + 1
+        } else {
+            first_row..first_row + 2
+        };
+        for row in rows {
+            let start = row * SCREEN_WIDTH_HIRES;
+            self.lines[start..start + SCREEN_WIDTH_HIRES].copy_from_slice(line);
+        }
+    }
+
+    /// Returns the current interlace field
+    pub fn field(&self) -> bool {
+        self.field
+    }
+
+    /// Starts a new frame in the other interlace field
+    pub fn start_frame(&mut self) {
+        self.field = !self.field;
+    }
+
+    /// Completes the current picture with `height` lines and makes it the one returned by
+    /// `framebuffer`
+    ///
+    /// The picture has twice the lines if `interlace` is set.
+    pub fn end_frame(&mut self, height: usize, interlace: bool) {
+        // Only output at hi-res width if it was actually used
+        let width = if self.hires {
+            SCREEN_WIDTH_HIRES
+        } else {
+            SCREEN_WIDTH
+        };
+        let step = SCREEN_WIDTH_HIRES / width;
+        // Both fields are always stored
+        let (height, row_step) = if interlace {
+            (height * 2, 1)
+        } else {
+            (height, 2)
+        };
+
+        let framebuffer = &mut self.framebuffer;
+        framebuffer.width = width;
+        framebuffer.height = height;
+        for (y, row) in self
+            .lines
+            .chunks(SCREEN_WIDTH_HIRES)
+            .step_by(row_step)
+            .take(height)
+            .enumerate()
+        {
+            for (x, color) in row.iter().step_by(step).enumerate() {
+                let i = (y * width + x) * 3;
+                framebuffer.pixels[i..i + 3].copy_from_slice(&rgb8(*color));
+            }
+        }
+        self.hires = false;
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Ppu {
+            lines: vec![0; SCREEN_WIDTH_HIRES * SCREEN_HEIGHT_INTERLACE].into_boxed_slice(),
+            hires: false,
+            field: false,
+            mosaic_counter: 0,
+            mosaic_y: 0,
+            cgram_fetches: [0; SCREEN_WIDTH],
+            framebuffer: Framebuffer::new(),
+        }
+    }
+}
+
+/// Returns `true` if the current BG mode outputs 512 pixels per line
+fn is_hires(io: &PpuIo) -> bool {
+    matches!(io.bg_mode & BGMODE_MODE, 5 | 6)
+}
+
+/// Returns the bit of `layer` in the layer enable registers
+fn layer_mask(layer: Layer) -> u8 {
+    match layer {
+        Layer::Bg1 => 0b0000_0001,
+        Layer::Bg2 => 0b0000_0010,
+        Layer::Bg3 => 0b0000_0100,
+        Layer::Bg4 => 0b0000_1000,
+        Layer::Obj => 0b0001_0000,
+    }
+}
+
+/// Keeps the pixels from `line` that are in front of the ones in `screen`
+fn merge(screen: &mut [Pixel], line: &[Pixel]) {
+    for (screen_px, px) in screen.iter_mut().zip(line.iter()) {
+        if px.z > screen_px.z {
+            *screen_px = *px;
+        }
+    }
+}
+
+/// Returns `true` if a pixel is in the area selected by a 2bit CGWSEL field
+///
+/// The area is never, outside the color window, inside it or always.
+fn in_color_area(area: u8, in_window: bool) -> bool {
+    match area {
+        0b00 => false,
+        0b01 => !in_window,
+        0b10 => in_window,
+        0b11 => true,
+        _ => unreachable!(),
+    }
+}
+
+/// Scales the channels of BGR555 `color` by `brightness` from 0 to 15
+fn apply_brightness(color: u16, brightness: u16) -> u16 {
+    let mut scaled = 0;
+    for shift in [0, 5, 10] {
+        let c = (color >> shift) & 0x1F;
+        scaled |= (c * brightness / 15) << shift;
+    }
+    scaled
+}
+
+/// Adds or subtracts BGR555 `sub` from `main`, optionally halving the result
+fn color_math(main: u16, sub: u16, subtract: bool, half: bool) -> u16 {
+    let mut color = 0;
+    for shift in [0, 5, 10] {
+        let m = (main >> shift) & 0x1F;
+        let s = (sub >> shift) & 0x1F;
+        let c = if subtract {
+            let c = m.saturating_sub(s);
+            if half {
+                c >> 1
+            } else {
+                c
+            }
+        } else {
+            let c = m + s;
+            if half {
+                c >> 1
+            } else {
+                c.min(0x1F)
+            }
+        };
+        color |= c << shift;
+    }
+    color
+}
+
+/// Makes the pixels of `line` inside `mask` transparent
+///
+/// Window positions are in normal resolution so each position covers two pixels on hi-res
+/// lines.
+fn clip_to_window(mask: &window::WindowMask, line: &mut [Pixel]) {
+    let scale = line.len() / SCREEN_WIDTH;
+    for (x, px) in line.iter_mut().enumerate() {
+        if mask[x / scale] {
+            *px = Pixel::TRANSPARENT;
+        }
+    }
+}
+
+/// Returns the depth of `layer` at `priority` in the current BG mode
+///
+/// The layers are ordered from front to back and the front one gets the highest depth. Returns 0
+/// if the layer isn't shown in the mode.
+fn layer_z(layer: Layer, priority: u8, io: &PpuIo) -> u8 {
+    use Layer::*;
+    let order: &[(Layer, u8)] = match io.bg_mode & BGMODE_MODE {
+        0 => &[
+            (Obj, 3),
+            (Bg1, 1),
+            (Bg2, 1),
+            (Obj, 2),
+            (Bg1, 0),
+            (Bg2, 0),
+            (Obj, 1),
+            (Bg3, 1),
+            (Bg4, 1),
+            (Obj, 0),
+            (Bg3, 0),
+            (Bg4, 0),
+        ],
+        1 if io.bg_mode & BGMODE_BG3_PRIORITY != 0 => &[
+            (Bg3, 1),
+            (Obj, 3),
+            (Bg1, 1),
+            (Bg2, 1),
+            (Obj, 2),
+            (Bg1, 0),
+            (Bg2, 0),
+            (Obj, 1),
+            (Obj, 0),
+            (Bg3, 0),
+        ],
+        1 => &[
+            (Obj, 3),
+            (Bg1, 1),
+            (Bg2, 1),
+            (Obj, 2),
+            (Bg1, 0),
+            (Bg2, 0),
+            (Obj, 1),
+            (Bg3, 1),
+            (Obj, 0),
+            (Bg3, 0),
+        ],
+        2..=6 => &[
+            (Obj, 3),
+            (Bg1, 1),
+            (Obj, 2),
+            (Bg2, 1),
+            (Obj, 1),
+            (Bg1, 0),
+            (Obj, 0),
+            (Bg2, 0),
+        ],
+        // BG2 is only shown with EXTBG and BG1 has no priority bit
+        7 => &[
+            (Obj, 3),
+            (Obj, 2),
+            (Bg2, 1),
+            (Obj, 1),
+            (Bg1, 0),
+            (Obj, 0),
+            (Bg2, 0),
+        ],
+        _ => unreachable!(),
+    };
+    order
+        .iter()
+        .position(|&(l, p)| l == layer && p == priority)
+        .map_or(0, |i| (order.len() - i) as u8)
+}
+
+/// Returns the VRAM word at `word_addr`, wrapping at the end of VRAM
+fn vram_word(vram: &[u8], word_addr: u16) -> u16 {
+    let addr = ((word_addr & 0x7FFF) as usize) << 1;
+    ((vram[addr + 1] as u16) << 8) | (vram[addr] as u16)
+}
+
+/// Returns the BGR555 color at `index` in CGRAM
+fn cgram_color(cgram: &[u8], index: u8) -> u16 {
+    let addr = (index as usize) << 1;
+    (((cgram[addr + 1] as u16) << 8) | (cgram[addr] as u16)) & 0x7FFF
+}
+
+/// Converts BGR555 `color` to 8bit RGB
+fn rgb8(color: u16) -> [u8; 3] {
+    let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+    [
+        expand(color & 0x1F),
+        expand((color >> 5) & 0x1F),
+        expand((color >> 10) & 0x1F),
+    ]
+}
+
+// INIDISP bits
+/// Forced blank
+const INIDISP_FORCED_BLANK: u8 = 0b1000_0000;
+/// Master brightness
+const INIDISP_BRIGHTNESS: u8 = 0b0000_1111;
+
+// CGWSEL bits
+/// Area where the main screen is forced black
+const CGWSEL_MAIN_BLACK: u8 = 0b1100_0000;
+/// Area where color math is disabled
+const CGWSEL_PREVENT_MATH: u8 = 0b0011_0000;
+/// Use sub screen instead of fixed color in color math
+const CGWSEL_ADD_SUB_SCREEN: u8 = 0b0000_0010;
+
+// CGADSUB bits
+/// Subtract instead of add
+const CGADSUB_SUBTRACT: u8 = 0b1000_0000;
+/// Halve the result
+const CGADSUB_HALF: u8 = 0b0100_0000;
+/// Color math on backdrop
+const CGADSUB_BACKDROP: u8 = 0b0010_0000;
+
+// SETINI bits
+/// Pseudo hi-res
+const SETINI_PSEUDO_HIRES: u8 = 0b0000_1000;
+/// OBJ interlace
+const SETINI_OBJ_INTERLACE: u8 = 0b0000_0010;
+/// Screen interlace
+const SETINI_INTERLACE: u8 = 0b0000_0001;
+
+// BGMODE bits
+/// BG mode
+const BGMODE_MODE: u8 = 0b0000_0111;
+/// Mode 1 BG3 priority
+const BGMODE_BG3_PRIORITY: u8 = 0b0000_1000;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders line `y` with `mosaic` and returns the line mosaic enabled BGs are rendered from
+    fn mosaic_line(ppu: &mut Ppu, y: u16, mosaic: u8) -> u16 {
+        let mut io = PpuIo::new();
+        io.mosaic = mosaic;
+        ppu.render_line(y, &mut io, &[0; 0x10000], &Oam::default(), &[0; 512]);
+        ppu.mosaic_y
+    }
+
+    #[test]
+    fn mosaic_size_change() {
+        let mut ppu = Ppu::default();
+        let lines: Vec<u16> = (1..=4).map(|y| mosaic_line(&mut ppu, y, 0x31)).collect();
+        assert_eq!(lines, vec![1, 1, 1, 1]);
+        // New size is only taken after the current block ends
+        let lines: Vec<u16> = (5..=9).map(|y| mosaic_line(&mut ppu, y, 0x11)).collect();
+        assert_eq!(lines, vec![5, 5, 7, 7, 9]);
+    }
+
+    #[test]
+    fn mosaic_mid_frame() {
+        let mut ppu = Ppu::default();
+        assert_eq!(mosaic_line(&mut ppu, 100, 0x11), 100);
+        assert_eq!(mosaic_line(&mut ppu, 101, 0x11), 100);
+        assert_eq!(mosaic_line(&mut ppu, 102, 0x11), 102);
+    }
+}
@@ -0,0 +1,152 @@
+use super::{cgram_color, layer_z, Layer, Pixel};
+use crate::ppu_io::PpuIo;
+
+/// Rotated and scaled 128x128 tile background of mode 7
+pub struct Mode7 {
+    /// Matrix parameters A, B, C, D as signed 8.8 fixed point
+    a: i32,
+    b: i32,
+    c: i32,
+    d: i32,
+    /// Center of the transform
+    center_x: i32,
+    center_y: i32,
+    hofs: i32,
+    vofs: i32,
+    h_flip: bool,
+    v_flip: bool,
+    /// What is shown outside the 1024x1024 pixel playfield
+    screen_over: ScreenOver,
+    /// `true` if the layer is BG2 of EXTBG, which uses bit 7 of the color index as priority
+    extbg: bool,
+    /// Depths for pixel priorities 0 and 1
+    z: [u8; 2],
+}
+
+/// Screen over settings of M7SEL
+#[derive(PartialEq)]
+enum ScreenOver {
+    Wrap,
+    Transparent,
+    Tile0,
+}
+
+impl Mode7 {
+    /// Gathers the settings of `layer` from `io`
+    ///
+    /// Returns `None` if the layer isn't shown in mode 7.
+    pub fn new(layer: Layer, io: &PpuIo) -> Option<Mode7> {
+        let extbg = match layer {
+            Layer::Bg1 => false,
+            Layer::Bg2 if io.setini & SETINI_EXTBG != 0 => true,
+            _ => return None,
+        };
+        let screen_over = match (io.m7_sel & M7SEL_SCREEN_OVER) >> 6 {
+            0b10 => ScreenOver::Transparent,
+            0b11 => ScreenOver::Tile0,
+            _ => ScreenOver::Wrap,
+        };
+
+        Some(Mode7 {
+            a: io.m7_a.value() as i16 as i32,
+            b: io.m7_b.value() as i16 as i32,
+            c: io.m7_c.value() as i16 as i32,
+            d: io.m7_d.value() as i16 as i32,
+            center_x: sign_extend13(io.m7_x.value()),
+            center_y: sign_extend13(io.m7_y.value()),
+            hofs: sign_extend13(io.m7_hofs),
+            vofs: sign_extend13(io.m7_vofs),
+            h_flip: io.m7_sel & M7SEL_H_FLIP != 0,
+            v_flip: io.m7_sel & M7SEL_V_FLIP != 0,
+            screen_over,
+            extbg,
+            z: [layer_z(layer, 0, io), layer_z(layer, 1, io)],
+        })
+    }
+
+    /// Renders scanline `y` of the layer into `line`
+    pub fn render_line(&self, y: u16, vram: &[u8], cgram: &[u8], line: &mut [Pixel]) {
+        let screen_y = if self.v_flip {
+            255 - y as i32
+        } else {
+            y as i32
+        };
+        // Start of the line in the playfield, the hardware drops the six lowest bits of each
+        // product
+        let offset_x = clip10(self.hofs - self.center_x);
+        let offset_y = clip10(self.vofs - self.center_y);
+        let origin_x = ((self.a * offset_x) & !63)
+            + ((self.b * offset_y) & !63)
+            + ((self.b * screen_y) & !63)
+            + (self.center_x << 8);
+        let origin_y = ((self.c * offset_x) & !63)
+            + ((self.d * offset_y) & !63)
+            + ((self.d * screen_y) & !63)
+            + (self.center_y << 8);
+
+        for (x, px) in line.iter_mut().enumerate() {
+            let screen_x = if self.h_flip {
+                255 - x as i32
+            } else {
+                x as i32
+            };
+            let pixel_x = (origin_x + self.a * screen_x) >> 8;
+            let pixel_y = (origin_y + self.c * screen_x) >> 8;
+
+            let out_of_bounds = (pixel_x | pixel_y) & !1023 != 0;
+            if out_of_bounds && self.screen_over == ScreenOver::Transparent {
+                continue;
+            }
+            // Tilemap is in the low bytes of the first 16K words, tiles in the high bytes
+            let tile = if out_of_bounds && self.screen_over == ScreenOver::Tile0 {
+                0
+            } else {
+                let tile_x = ((pixel_x >> 3) & 0x7F) as usize;
+                let tile_y = ((pixel_y >> 3) & 0x7F) as usize;
+                vram[(tile_y * 128 + tile_x) << 1] as usize
+            };
+            let tile_pixel = (((pixel_y & 0b111) << 3) | (pixel_x & 0b111)) as usize;
+            let mut index = vram[(((tile << 6) | tile_pixel) << 1) + 1];
+
+            let mut priority = 0;
+            if self.extbg {
+                priority = (index >> 7) as usize;
+                index &= 0x7F;
+            }
+            if index != 0 {
+                *px = Pixel {
+                    color: cgram_color(cgram, index),
+                    cgram_index: index,
+                    z: self.z[priority],
+                    color_math: true,
+                };
+            }
+        }
+    }
+}
+
+/// Sign extends the 13bit value in `value`
+fn sign_extend13(value: u16) -> i32 {
+    (((value as i16) << 3) >> 3) as i32
+}
+
+/// Clips the 14bit signed `value` to 10 bits
+fn clip10(value: i32) -> i32 {
+    if value & 0x2000 != 0 {
+        value | !1023
+    } else {
+        value & 1023
+    }
+}
+
+// M7SEL bits
+/// Screen over behavior
+const M7SEL_SCREEN_OVER: u8 = 0b1100_0000;
+/// Vertical flip
+const M7SEL_V_FLIP: u8 = 0b0000_0010;
+/// Horizontal flip
+const M7SEL_H_FLIP: u8 = 0b0000_0001;
+
+// SETINI bits
+/// Mode 7 EXTBG
+const SETINI_EXTBG: u8 = 0b0100_0000;
@@ -0,0 +1,224 @@
+use super::{cgram_color, layer_z, vram_word, Layer, Pixel, SCREEN_WIDTH};
+use crate::oam::Oam;
+use crate::ppu_io::{PpuIo, STAT77_RANGE_OVER, STAT77_TIME_OVER};
+
+/// Sprites in OAM
+const SPRITE_COUNT: usize = 128;
+/// Sprites that can be in range on a single line
+const MAX_SPRITES_PER_LINE: usize = 32;
+/// 8 pixel wide slices of sprites that can be drawn on a single line
+const MAX_SLIVERS_PER_LINE: usize = 34;
+/// Offset of the high table in OAM
+const HIGH_TABLE_OFFSET: usize = 512;
+/// Index of the first OBJ color in CGRAM
+const OBJ_PALETTE_BASE: u8 = 128;
+
+/// Single entry in OAM
+struct Sprite {
+    /// 9bit X position, values above 255 are to the left of the screen
+    x: u16,
+    y: u8,
+    tile: u8,
+    /// `true` if tile number is in the second name table
+    name_select: bool,
+    palette: u8,
+    priority: u8,
+    h_flip: bool,
+    v_flip: bool,
+    width: u16,
+    height: u16,
+}
+
+impl Sprite {
+    fn new(index: usize, oam: &[u8], sizes: [(u16, u16); 2]) -> Sprite {
+        let bytes = &oam[index * 4..(index + 1) * 4];
+        let high_bits = (oam[HIGH_TABLE_OFFSET + index / 4] >> ((index % 4) * 2)) & 0b11;
+        let (width, height) = sizes[(high_bits >> 1) as usize];
+        // Attributes are vhoopppN
+        let attributes = bytes[3];
+        Sprite {
+            x: (((high_bits & 0b1) as u16) << 8) | (bytes[0] as u16),
+            y: bytes[1],
+            tile: bytes[2],
+            name_select: attributes & 0b1 != 0,
+            palette: (attributes >> 1) & 0b111,
+            priority: (attributes >> 4) & 0b11,
+            h_flip: attributes & 0b0100_0000 != 0,
+            v_flip: attributes & 0b1000_0000 != 0,
+            width,
+            height,
+        }
+    }
+
+    /// Returns `true` if the sprite is evaluated on line `row`
+    ///
+    /// Interlaced sprites cover half the lines as each field only shows every other row.
+    fn in_range(&self, row: u16, interlace: bool) -> bool {
+        // Sprites starting at X=256 count against the limit even though they are fully hidden
+        let off_screen = self.x > 256 && self.x + self.width - 1 < 512;
+        let height = if interlace {
+            self.height / 2
+        } else {
+            self.height
+        };
+        !off_screen && row.wrapping_sub(self.y as u16) & 0xFF < height
+    }
+}
+
+/// 8 pixel wide slice of a sprite on the current line
+struct Sliver {
+    /// Screen X of the leftmost pixel, may be negative
+    x: i16,
+    /// Word address of the tile row
+    addr: u16,
+    h_flip: bool,
+    palette: u8,
+    priority: u8,
+}
+
+/// Renders the sprites of screen line `y`, where the first visible line is 1
+///
+/// `field` is the current field if OBJ interlace is enabled. Returns the range over and time over
+/// flags in STAT77 format.
+pub fn render_line(
+    y: u16,
+    field: Option<bool>,
+    io: &PpuIo,
+    vram: &[u8],
+    oam: &Oam,
+    cgram: &[u8],
+    line: &mut [Pixel],
+) -> u8 {
+    // Sprites are drawn one line lower than their Y
+    let row = y - 1;
+    let sizes = obj_sizes(io.ob_sel);
+    let mem = oam.mem();
+
+    let mut flags = 0;
+    let mut in_range = Vec::with_capacity(MAX_SPRITES_PER_LINE);
+    let first = oam.first_sprite();
+    for i in 0..SPRITE_COUNT {
+        let sprite = Sprite::new((first + i) % SPRITE_COUNT, mem, sizes);
+        if sprite.in_range(row, field.is_some()) {
+            if in_range.len() == MAX_SPRITES_PER_LINE {
+                flags |= STAT77_RANGE_OVER;
+                break;
+            }
+            in_range.push(sprite);
+        }
+    }
+
+    // Tiles are fetched starting from the last sprite in range so the ones with highest
+    // priority lose theirs if there are too many
+    let name_base = ((io.ob_sel & OBSEL_NAME_BASE) as u16) << 13;
+    let name_gap = ((((io.ob_sel & OBSEL_NAME_SELECT) >> 3) as u16) + 1) << 12;
+    let mut slivers = Vec::with_capacity(MAX_SLIVERS_PER_LINE);
+    'fetch: for sprite in in_range.iter().rev() {
+        let mut sprite_row = row.wrapping_sub(sprite.y as u16) & 0xFF;
+        if let Some(field) = field {
+            sprite_row = sprite_row * 2 + field as u16;
+        }
+        if sprite.v_flip {
+            sprite_row = sprite.height - 1 - sprite_row;
+        }
+        let x = if sprite.x >= 256 {
+            sprite.x as i16 - 512
+        } else {
+            sprite.x as i16
+        };
+        let columns = sprite.width / 8;
+        for column in 0..columns {
+            let sliver_x = x + (column as i16) * 8;
+            if sliver_x <= -8 || sliver_x >= SCREEN_WIDTH as i16 {
+                continue;
+            }
+            if slivers.len() == MAX_SLIVERS_PER_LINE {
+                flags |= STAT77_TIME_OVER;
+                break 'fetch;
+            }
+
+            let tile_column = if sprite.h_flip {
+                columns - 1 - column
+            } else {
+                column
+            };
+            // Sprite tiles are laid out in the 16x16 tile name table, wrapping within the rows
+            // and columns
+            let tile_x = ((sprite.tile & 0x0F) as u16 + tile_column) & 0x0F;
+            let tile_y = ((sprite.tile >> 4) as u16 + sprite_row / 8) & 0x0F;
+            let mut table = name_base;
+            if sprite.name_select {
+                table = table.wrapping_add(name_gap);
+            }
+            let addr = table.wrapping_add((((tile_y << 4) | tile_x) << 4) + sprite_row % 8);
+
+            slivers.push(Sliver {
+                x: sliver_x,
+                addr,
+                h_flip: sprite.h_flip,
+                palette: sprite.palette,
+                priority: sprite.priority,
+            });
+        }
+    }
+
+    let z = [
+        layer_z(Layer::Obj, 0, io),
+        layer_z(Layer::Obj, 1, io),
+        layer_z(Layer::Obj, 2, io),
+        layer_z(Layer::Obj, 3, io),
+    ];
+    // Slivers of the earlier sprites are fetched last and drawn on top
+    for sliver in &slivers {
+        let planes = [
+            vram_word(vram, sliver.addr),
+            vram_word(vram, sliver.addr.wrapping_add(8)),
+        ];
+        for px in 0..8 {
+            let x = sliver.x + px;
+            if x < 0 || x >= SCREEN_WIDTH as i16 {
+                continue;
+            }
+            let bit = if sliver.h_flip { px } else { 7 - px };
+            let index = (planes[0] >> bit) & 0b1
+                | ((planes[0] >> (bit + 8)) & 0b1) << 1
+                | ((planes[1] >> bit) & 0b1) << 2
+                | ((planes[1] >> (bit + 8)) & 0b1) << 3;
+            if index != 0 {
+                let cgram_index = OBJ_PALETTE_BASE + (sliver.palette << 4) + index as u8;
+                line[x as usize] = Pixel {
+                    color: cgram_color(cgram, cgram_index),
+                    cgram_index,
+                    z: z[sliver.priority as usize],
+                    // Palettes 0-3 are never affected by color math
+                    color_math: sliver.palette >= 4,
+                };
+            }
+        }
+    }
+
+    flags
+}
+
+/// Returns the small and large sprite sizes selected in `ob_sel`
+fn obj_sizes(ob_sel: u8) -> [(u16, u16); 2] {
+    match (ob_sel & OBSEL_SIZE) >> 5 {
+        0 => [(8, 8), (16, 16)],
+        1 => [(8, 8), (32, 32)],
+        2 => [(8, 8), (64, 64)],
+        3 => [(16, 16), (32, 32)],
+        4 => [(16, 16), (64, 64)],
+        5 => [(32, 32), (64, 64)],
+        6 => [(16, 32), (32, 64)],
+        7 => [(16, 32), (32, 32)],
+        _ => unreachable!(),
+    }
+}
+
+// OBSEL bits
+/// Sprite sizes
+const OBSEL_SIZE: u8 = 0b1110_0000;
+/// Gap between the name tables
+const OBSEL_NAME_SELECT: u8 = 0b0001_1000;
+/// Name base address
+const OBSEL_NAME_BASE: u8 = 0b0000_0111;
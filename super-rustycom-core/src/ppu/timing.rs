@@ -0,0 +1,72 @@
+/// Master clock ticks it takes the beam to move by one dot
+const TICKS_PER_DOT: u32 = 4;
+/// Dots per scanline, including horizontal blanking
+pub const DOTS_PER_LINE: u16 = 340;
+/// Scanlines per NTSC frame, including vertical blanking
+pub const LINES_PER_FRAME: u16 = 262;
+/// Dot where the H-blank flag is set
+pub const HBLANK_START: u16 = 274;
+/// Dot where the H-blank flag is cleared
+pub const HBLANK_END: u16 = 1;
+/// First line of V-blank when 224 lines are displayed
+pub const VBLANK_START: u16 = 225;
+/// First line of V-blank when overscan is on and 239 lines are displayed
+pub const VBLANK_START_OVERSCAN: u16 = 240;
+
+/// Tracks the position of the beam in the 340x262 NTSC frame from master clock ticks
+pub struct Timing {
+    /// Master clock ticks accumulated towards the next dot
+    ticks: u32,
+    /// Horizontal position in dots
+    h: u16,
+    /// Vertical position in scanlines
+    v: u16,
+}
+
+impl Timing {
+    /// Returns the current H counter value
+    pub fn h(&self) -> u16 {
+        self.h
+    }
+
+    /// Returns the current V counter value
+    pub fn v(&self) -> u16 {
+        self.v
+    }
+
+    /// Adds `ticks` master clock ticks to be consumed by `step_dot`
+    pub fn add_ticks(&mut self, ticks: u32) {
+        self.ticks += ticks;
+    }
+
+    /// Moves the beam forward by one dot if enough ticks have been accumulated
+    ///
+    /// Returns `true` if the beam moved.
+    pub fn step_dot(&mut self) -> bool {
+        if self.ticks < TICKS_PER_DOT {
+            return false;
+        }
+        self.ticks -= TICKS_PER_DOT;
+
+        self.h += 1;
+        if self.h == DOTS_PER_LINE {
+            self.h = 0;
+            self.v += 1;
+            if self.v == LINES_PER_FRAME {
+                self.v = 0;
+            }
+        }
+        true
+    }
+}
+
+impl Default for Timing {
+    /// Initializes a new instance at the start of a frame
+    fn default() -> Self {
+        Timing {
+            ticks: 0,
+            h: 0,
+            v: 0,
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use super::{Layer, SCREEN_WIDTH};
+use crate::ppu_io::PpuIo;
+
+/// Area covered by windows, `true` for the pixels inside
+pub type WindowMask = [bool; SCREEN_WIDTH];
+
+/// Returns the combined window area of `layer`
+pub fn layer_mask(layer: Layer, io: &PpuIo) -> WindowMask {
+    let (sel, log) = match layer {
+        Layer::Bg1 => (io.w12_sel, io.wbg_log),
+        Layer::Bg2 => (io.w12_sel >> 4, io.wbg_log >> 2),
+        Layer::Bg3 => (io.w34_sel, io.wbg_log >> 4),
+        Layer::Bg4 => (io.w34_sel >> 4, io.wbg_log >> 6),
+        Layer::Obj => (io.wobj_sel, io.wobj_log),
+    };
+    mask(sel, log, io)
+}
+
+/// Returns the area of the color window used by color math
+pub fn color_mask(io: &PpuIo) -> WindowMask {
+    mask(io.wobj_sel >> 4, io.wobj_log >> 2, io)
+}
+
+/// Returns the area covered by the windows enabled in the lower nibble of `sel` when combined
+/// with the logic in the lower two bits of `log`
+fn mask(sel: u8, log: u8, io: &PpuIo) -> WindowMask {
+    let w1 = window(
+        io.wh0,
+        io.wh1,
+        sel & SEL_W1_ENABLE != 0,
+        sel & SEL_W1_INVERT != 0,
+    );
+    let w2 = window(
+        io.wh2,
+        io.wh3,
+        sel & SEL_W2_ENABLE != 0,
+        sel & SEL_W2_INVERT != 0,
+    );
+
+    let mut mask = [false; SCREEN_WIDTH];
+    for (x, m) in mask.iter_mut().enumerate() {
+        *m = match (w1, w2) {
+            (None, None) => false,
+            (Some(w1), None) => w1.contains(x),
+            (None, Some(w2)) => w2.contains(x),
+            (Some(w1), Some(w2)) => {
+                let (in1, in2) = (w1.contains(x), w2.contains(x));
+                match log & 0b11 {
+                    LOG_OR => in1 || in2,
+                    LOG_AND => in1 && in2,
+                    LOG_XOR => in1 != in2,
+                    LOG_XNOR => in1 == in2,
+                    _ => unreachable!(),
+                }
+            }
+        };
+    }
+    mask
+}
+
+/// Single window, inside from `left` to `right` inclusive
+#[derive(Clone, Copy)]
+struct Window {
+    left: u8,
+    right: u8,
+    invert: bool,
+}
+
+impl Window {
+    fn contains(&self, x: usize) -> bool {
+        // Window is empty if left is past right
+        let inside = (self.left as usize) <= x && x <= (self.right as usize);
+        inside != self.invert
+    }
+}
+
+/// Returns the window from `left` to `right` if it is enabled
+fn window(left: u8, right: u8, enabled: bool, invert: bool) -> Option<Window> {
+    if enabled {
+        Some(Window {
+            left,
+            right,
+            invert,
+        })
+    } else {
+        None
+    }
+}
+
+// Window select bits for a single layer
+/// Invert window 1
+const SEL_W1_INVERT: u8 = 0b0001;
+/// Enable window 1
+const SEL_W1_ENABLE: u8 = 0b0010;
+/// Invert window 2
+const SEL_W2_INVERT: u8 = 0b0100;
+/// Enable window 2
+const SEL_W2_ENABLE: u8 = 0b1000;
+
+// Window logic values
+const LOG_OR: u8 = 0b00;
+const LOG_AND: u8 = 0b01;
+const LOG_XOR: u8 = 0b10;
+const LOG_XNOR: u8 = 0b11;
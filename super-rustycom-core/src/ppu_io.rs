@@ -35,6 +35,11 @@ pub struct PpuIo {
     pub m7_d: DoubleReg,
     pub m7_x: DoubleReg,
     pub m7_y: DoubleReg,
+    /// Mode 7 scroll, written through BG1HOFS and BG1VOFS
+    pub m7_hofs: u16,
+    pub m7_vofs: u16,
+    /// Previous byte written to the mode 7 registers
+    m7_latch: u8,
     pub w12_sel: u8,
     pub w34_sel: u8,
     pub wobj_sel: u8,
@@ -51,11 +56,10 @@ pub struct PpuIo {
     pub cg_wsel: u8,
     pub cg_adsub: u8,
     pub col_data: u8,
+    /// BGR555 fixed color set through COLDATA
+    pub fixed_color: u16,
     pub setini: u8,
     // Cpu read
-    pub mpy_l: u8,
-    pub mpy_m: u8,
-    pub mpy_h: u8,
     pub sl_hv: u8,
     pub rd_oam: DoubleReg,
     pub rd_vram_l: u8,
@@ -104,6 +108,9 @@ impl PpuIo {
             m7_d: DoubleReg::new(),
             m7_x: DoubleReg::new(),
             m7_y: DoubleReg::new(),
+            m7_hofs: 0x0000,
+            m7_vofs: 0x0000,
+            m7_latch: 0x00,
             w12_sel: 0x00,
             w34_sel: 0x00,
             wobj_sel: 0x00,
@@ -120,10 +127,8 @@ impl PpuIo {
             cg_wsel: 0x00,
             cg_adsub: 0x00,
             col_data: 0x00,
+            fixed_color: 0x0000,
             setini: 0x00,
-            mpy_l: 0x01,
-            mpy_m: 0x00,
-            mpy_h: 0x00,
             sl_hv: 0x00,
             rd_oam: DoubleReg::new(),
             rd_vram_l: 0x00,
@@ -197,9 +202,9 @@ impl PpuIo {
             mmap::CGADSUB => self.cg_adsub,
             mmap::COLDATA => self.col_data,
             mmap::SETINI => self.setini,
-            mmap::MPYL => self.mpy_l,
-            mmap::MPYM => self.mpy_m,
-            mmap::MPYH => self.mpy_h,
+            mmap::MPYL => self.mpy() as u8,
+            mmap::MPYM => (self.mpy() >> 8) as u8,
+            mmap::MPYH => (self.mpy() >> 16) as u8,
             mmap::SLHV => self.sl_hv,
             mmap::RDOAM => self.rd_oam.read(),
             mmap::RDVRAML => self.rd_vram_l,
@@ -208,11 +213,41 @@ impl PpuIo {
             mmap::OPHCT => self.op_hct.read(),
             mmap::OPVCT => self.op_vct.read(),
             mmap::STAT77 => self.stat_77,
-            mmap::STAT78 => self.stat_78,
+            mmap::STAT78 => {
+                // Reading resets the latch flag and the OPHCT/OPVCT byte selection
+                let value = self.stat_78;
+                self.stat_78 &= !STAT78_LATCH;
+                self.op_hct.reset_access();
+                self.op_vct.reset_access();
+                value
+            }
             _ => unreachable!(),
         }
     }
 
+    /// Returns the full value of a mode 7 register written with `value`
+    ///
+    /// Mode 7 registers share a single latch that holds the previous byte written to any of
+    /// them.
+    fn write_m7(&mut self, value: u8) -> u16 {
+        let full = ((value as u16) << 8) | (self.m7_latch as u16);
+        self.m7_latch = value;
+        full
+    }
+
+    /// Returns the signed 24bit product of M7A and the last byte written to M7B
+    fn mpy(&self) -> u32 {
+        let m7_b = (self.m7_b.value() >> 8) as u8 as i8;
+        ((self.m7_a.value() as i16 as i32) * (m7_b as i32)) as u32 & 0x00FF_FFFF
+    }
+
+    /// Latches `h` and `v` to OPHCT and OPVCT
+    pub fn latch_counters(&mut self, h: u16, v: u16) {
+        self.op_hct.set_value(h);
+        self.op_vct.set_value(v);
+        self.stat_78 |= STAT78_LATCH;
+    }
+
     pub fn peek(&self, addr: usize) -> u8 {
         match addr {
             mmap::INIDISP => self.ini_disp,
@@ -265,9 +300,9 @@ impl PpuIo {
             mmap::CGADSUB => self.cg_adsub,
             mmap::COLDATA => self.col_data,
             mmap::SETINI => self.setini,
-            mmap::MPYL => self.mpy_l,
-            mmap::MPYM => self.mpy_m,
-            mmap::MPYH => self.mpy_h,
+            mmap::MPYL => self.mpy() as u8,
+            mmap::MPYM => (self.mpy() >> 8) as u8,
+            mmap::MPYH => (self.mpy() >> 16) as u8,
             mmap::SLHV => self.sl_hv,
             mmap::RDOAM => self.rd_oam.peek(),
             mmap::RDVRAML => self.rd_vram_l,
@@ -296,8 +331,14 @@ impl PpuIo {
             mmap::BG4SC => self.bg4_sc = value,
             mmap::BG12NBA => self.bg12_nba = value,
             mmap::BG34NBA => self.bg34_nba = value,
-            mmap::BG1HOFS => self.bg1_hofs.write(value),
-            mmap::BG1VOFS => self.bg1_vofs.write(value),
+            mmap::BG1HOFS => {
+                self.bg1_hofs.write(value);
+                self.m7_hofs = self.write_m7(value);
+            }
+            mmap::BG1VOFS => {
+                self.bg1_vofs.write(value);
+                self.m7_vofs = self.write_m7(value);
+            }
             mmap::BG2HOFS => self.bg2_hofs.write(value),
             mmap::BG2VOFS => self.bg2_vofs.write(value),
             mmap::BG3HOFS => self.bg3_hofs.write(value),
@@ -305,12 +346,30 @@ impl PpuIo {
             mmap::BG4HOFS => self.bg4_hofs.write(value),
             mmap::BG4VOFS => self.bg4_vofs.write(value),
             mmap::M7SEL => self.m7_sel = value,
-            mmap::M7A => self.m7_a.write(value),
-            mmap::M7B => self.m7_b.write(value),
-            mmap::M7C => self.m7_c.write(value),
-            mmap::M7D => self.m7_d.write(value),
-            mmap::M7X => self.m7_x.write(value),
-            mmap::M7Y => self.m7_y.write(value),
+            mmap::M7A => {
+                let value = self.write_m7(value);
+                self.m7_a.set_value(value)
+            }
+            mmap::M7B => {
+                let value = self.write_m7(value);
+                self.m7_b.set_value(value)
+            }
+            mmap::M7C => {
+                let value = self.write_m7(value);
+                self.m7_c.set_value(value)
+            }
+            mmap::M7D => {
+                let value = self.write_m7(value);
+                self.m7_d.set_value(value)
+            }
+            mmap::M7X => {
+                let value = self.write_m7(value);
+                self.m7_x.set_value(value)
+            }
+            mmap::M7Y => {
+                let value = self.write_m7(value);
+                self.m7_y.set_value(value)
+            }
             mmap::W12SEL => self.w12_sel = value,
             mmap::W34SEL => self.w34_sel = value,
             mmap::WOBJSEL => self.wobj_sel = value,
@@ -326,11 +385,20 @@ impl PpuIo {
             mmap::TSW => self.tsw = value,
             mmap::CGWSEL => self.cg_wsel = value,
             mmap::CGADSUB => self.cg_adsub = value,
-            mmap::COLDATA => self.col_data = value,
+            mmap::COLDATA => {
+                self.col_data = value;
+                // Intensity is written to each of the channels selected by the upper bits
+                let intensity = (value & 0x1F) as u16;
+                for (bit, shift) in [(0b0010_0000, 0), (0b0100_0000, 5), (0b1000_0000, 10)] {
+                    if value & bit != 0 {
+                        self.fixed_color =
+                            (self.fixed_color & !(0x1F << shift)) | (intensity << shift);
+                    }
+                }
+            }
             mmap::SETINI => self.setini = value,
-            mmap::MPYL => self.mpy_l = value,
-            mmap::MPYM => self.mpy_m = value,
-            mmap::MPYH => self.mpy_h = value,
+            // Multiplication result is computed from M7A and M7B
+            mmap::MPYL | mmap::MPYM | mmap::MPYH => (),
             mmap::SLHV => self.sl_hv = value,
             mmap::RDOAM => self.rd_oam.write(value),
             mmap::RDVRAML => self.rd_vram_l = value,
@@ -345,6 +413,15 @@ impl PpuIo {
     }
 }
 
+/// Too many sprites on a line flag in STAT77
+pub const STAT77_RANGE_OVER: u8 = 0b0100_0000;
+/// Too many sprite tiles on a line flag in STAT77
+pub const STAT77_TIME_OVER: u8 = 0b1000_0000;
+/// Interlace field flag in STAT78
+pub const STAT78_FIELD: u8 = 0b1000_0000;
+/// Counter latch flag in STAT78
+const STAT78_LATCH: u8 = 0b0100_0000;
+
 pub struct DoubleReg {
     value: u16,
     high_active: bool, // TODO: Should there be separate flags for read and write?
@@ -372,6 +449,21 @@ impl DoubleReg {
         }
     }
 
+    /// Returns the full value
+    pub fn value(&self) -> u16 {
+        self.value
+    }
+
+    /// Sets the full value without affecting the active byte
+    pub fn set_value(&mut self, value: u16) {
+        self.value = value;
+    }
+
+    /// Makes the next access target the low byte
+    pub fn reset_access(&mut self) {
+        self.high_active = false;
+    }
+
     pub fn write(&mut self, value: u8) {
         if self.high_active {
             self.value = (self.value & 0x00FF) | ((value as u16) << 8);
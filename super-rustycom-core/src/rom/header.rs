@@ -0,0 +1,276 @@
+use super::{
+    Mapping, RomError, EXHIROM_HEADER, EXLOROM_HEADER, EXTENDED_BASE, HIROM_HEADER, LOROM_HEADER,
+};
+
+/// Cartridge header found at the end of the first ROM bank
+#[derive(Clone, Debug)]
+pub struct RomHeader {
+    pub title: String,
+    pub mapping: Mapping,
+    /// `true` if the ROM can be accessed at FastROM speed
+    pub fast: bool,
+    pub chipset: Chipset,
+    /// Size of cartridge RAM in bytes
+    pub ram_size: usize,
+    pub region: Region,
+    pub version: u8,
+}
+
+/// Hardware in the cartridge besides the ROM
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chipset {
+    pub ram: bool,
+    pub battery: bool,
+    pub coprocessor: Option<Coprocessor>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coprocessor {
+    Dsp,
+    SuperFx,
+    Obc1,
+    Sa1,
+    Sdd1,
+    Srtc,
+    Other,
+    Custom,
+}
+
+/// Destination region of the cartridge
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Japan,
+    NorthAmerica,
+    Europe,
+    Sweden,
+    Finland,
+    Denmark,
+    France,
+    Netherlands,
+    Spain,
+    Germany,
+    Italy,
+    China,
+    Indonesia,
+    Korea,
+    Global,
+    Canada,
+    Brazil,
+    Australia,
+    Unknown(u8),
+}
+
+impl Region {
+    /// Returns `true` if the region uses PAL consoles
+    pub fn is_pal(self) -> bool {
+        !matches!(
+            self,
+            Region::Japan
+                | Region::NorthAmerica
+                | Region::Korea
+                | Region::Canada
+                | Region::Brazil
+                | Region::Unknown(_)
+        )
+    }
+}
+
+impl RomHeader {
+    /// Finds the most plausible header in `rom`
+    ///
+    /// Each possible location is scored on how sensible its contents are as the location alone
+    /// can't tell the mapping apart. `rom` is expected to be without a copier header.
+    pub fn parse(rom: &[u8]) -> Result<RomHeader, RomError> {
+        // Plain mappings are listed first as they win ties
+        let candidates = [
+            (Mapping::LoRom, LOROM_HEADER),
+            (Mapping::HiRom, HIROM_HEADER),
+            (Mapping::ExLoRom, EXLOROM_HEADER),
+            (Mapping::ExHiRom, EXHIROM_HEADER),
+        ];
+        // Expanded fan translations often keep the original LoROM map mode, which is only
+        // trusted when the ROM is expanded and the LoROM location doesn't look like a header
+        let lorom_valid = LOROM_HEADER + HEADER_SIZE <= rom.len()
+            && score(
+                &rom[LOROM_HEADER..LOROM_HEADER + HEADER_SIZE],
+                Mapping::LoRom,
+                false,
+            ) > 0;
+        let expanded_lorom = rom.len() > EXTENDED_BASE && !lorom_valid;
+
+        // Only positive scores are considered plausible
+        let mut best_score = 0;
+        let mut best = None;
+        for &(mapping, offset) in &candidates {
+            if offset + HEADER_SIZE > rom.len() {
+                continue;
+            }
+            let score = score(&rom[offset..offset + HEADER_SIZE], mapping, expanded_lorom);
+            if score > best_score {
+                best_score = score;
+                best = Some((mapping, offset));
+            }
+        }
+
+        best.map(|(mapping, offset)| RomHeader::new(&rom[offset..offset + HEADER_SIZE], mapping))
+            .ok_or(RomError::NoHeader)
+    }
+
+    fn new(header: &[u8], mapping: Mapping) -> RomHeader {
+        // Titles are ASCII with JIS X 0201 katakana in Japanese ones
+        let title: String = header[..TITLE_LEN]
+            .iter()
+            .map(|&c| {
+                if (0x20..0x7F).contains(&c) {
+                    c as char
+                } else {
+                    '?'
+                }
+            })
+            .collect();
+        let ram_size = match header[RAM_SIZE] {
+            0 => 0,
+            // Size is given as log2 of kilobytes, anything past 128K is bogus
+            size => 1024 << size.min(7),
+        };
+
+        RomHeader {
+            title: title.trim_end().to_string(),
+            mapping,
+            fast: header[MAKEUP] & MAKEUP_FAST != 0,
+            chipset: Chipset::new(header[CHIPSET]),
+            ram_size,
+            region: Region::new(header[REGION]),
+            version: header[VERSION],
+        }
+    }
+}
+
+impl Chipset {
+    fn new(value: u8) -> Chipset {
+        // Lower nibble lists the parts, upper one is the type of the coprocessor
+        let (ram, battery, has_coprocessor) = match value & 0x0F {
+            0x0 => (false, false, false),
+            0x1 => (true, false, false),
+            0x2 => (true, true, false),
+            0x3 => (false, false, true),
+            0x4 => (true, false, true),
+            0x5 => (true, true, true),
+            0x6 => (false, true, true),
+            _ => (false, false, false),
+        };
+        let coprocessor = if has_coprocessor {
+            Some(match value >> 4 {
+                0x0 => Coprocessor::Dsp,
+                0x1 => Coprocessor::SuperFx,
+                0x2 => Coprocessor::Obc1,
+                0x3 => Coprocessor::Sa1,
+                0x4 => Coprocessor::Sdd1,
+                0x5 => Coprocessor::Srtc,
+                0xF => Coprocessor::Custom,
+                _ => Coprocessor::Other,
+            })
+        } else {
+            None
+        };
+        Chipset {
+            ram,
+            battery,
+            coprocessor,
+        }
+    }
+}
+
+impl Region {
+    fn new(value: u8) -> Region {
+        match value {
+            0x00 => Region::Japan,
+            0x01 => Region::NorthAmerica,
+            0x02 => Region::Europe,
+            0x03 => Region::Sweden,
+            0x04 => Region::Finland,
+            0x05 => Region::Denmark,
+            0x06 => Region::France,
+            0x07 => Region::Netherlands,
+            0x08 => Region::Spain,
+            0x09 => Region::Germany,
+            0x0A => Region::Italy,
+            0x0B => Region::China,
+            0x0C => Region::Indonesia,
+            0x0D => Region::Korea,
+            0x0E => Region::Global,
+            0x0F => Region::Canada,
+            0x10 => Region::Brazil,
+            0x11 => Region::Australia,
+            _ => Region::Unknown(value),
+        }
+    }
+}
+
+/// Returns how plausible `header` is for `mapping`, positive scores are likely headers
+///
+/// `expanded_lorom` allows the LoROM map mode for ExLoROM.
+fn score(header: &[u8], mapping: Mapping, expanded_lorom: bool) -> i32 {
+    let mut score = 0;
+
+    let complement = word(header, COMPLEMENT);
+    let checksum = word(header, CHECKSUM);
+    if complement ^ checksum == 0xFFFF {
+        score += 4;
+    }
+
+    let map_mode = header[MAKEUP] & !MAKEUP_FAST;
+    let expected = match mapping {
+        Mapping::LoRom => MAP_MODE_LOROM,
+        Mapping::HiRom => MAP_MODE_HIROM,
+        Mapping::ExLoRom => MAP_MODE_EXLOROM,
+        Mapping::ExHiRom => MAP_MODE_EXHIROM,
+    };
+    let expanded = mapping == Mapping::ExLoRom && map_mode == MAP_MODE_LOROM && expanded_lorom;
+    if map_mode == expected || expanded {
+        score += 2;
+    } else if map_mode & 0xE0 != 0x20 {
+        score -= 2;
+    }
+
+    // Execution has to start from ROM in the system banks
+    if word(header, RESET_VECTOR) >= 0x8000 {
+        score += 2;
+    } else {
+        score -= 4;
+    }
+
+    if header[..TITLE_LEN].iter().all(|&c| c >= 0x20) {
+        score += 1;
+    }
+    if header[RAM_SIZE] <= 0x07 {
+        score += 1;
+    }
+
+    score
+}
+
+fn word(header: &[u8], offset: usize) -> u16 {
+    (header[offset] as u16) | ((header[offset + 1] as u16) << 8)
+}
+
+/// Header including the interrupt vectors
+const HEADER_SIZE: usize = 0x40;
+
+// Offsets in the header
+const TITLE_LEN: usize = 21;
+const MAKEUP: usize = 0x15;
+const CHIPSET: usize = 0x16;
+const RAM_SIZE: usize = 0x18;
+const REGION: usize = 0x19;
+const VERSION: usize = 0x1B;
+const COMPLEMENT: usize = 0x1C;
+const CHECKSUM: usize = 0x1E;
+const RESET_VECTOR: usize = 0x3C;
+
+// Makeup byte values
+const MAKEUP_FAST: u8 = 0b0001_0000;
+const MAP_MODE_LOROM: u8 = 0x20;
+const MAP_MODE_HIROM: u8 = 0x21;
+const MAP_MODE_EXLOROM: u8 = 0x22;
+const MAP_MODE_EXHIROM: u8 = 0x25;
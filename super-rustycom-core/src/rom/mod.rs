@@ -0,0 +1,474 @@
+mod header;
+
+pub use header::{Chipset, Coprocessor, Region, RomHeader};
+
+use crate::mmap;
+use log::{info, warn};
+use std::fmt;
+
+/// Layout of the ROM in the CPU address space
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    /// 32K chunks in the upper halves of banks $00-$7D and $80-$FF
+    LoRom,
+    /// Full 64K banks in $40-$7D and $C0-$FF, mirrored to the upper halves of $00-$3F and $80-$BF
+    HiRom,
+    /// LoROM with the first 4M in banks $80-$FF and the rest in $00-$7D
+    ExLoRom,
+    /// HiROM with the first 4M in banks $C0-$FF and the rest in $40-$7D
+    ExHiRom,
+}
+
+/// Reason a ROM couldn't be loaded
+#[derive(Debug)]
+pub enum RomError {
+    /// ROM is smaller than a single LoROM bank, holds the size without a copier header
+    TooSmall(usize),
+    /// No plausible header was found in any of the possible locations
+    NoHeader,
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::TooSmall(size) => write!(
+                f,
+                "ROM is too small at {} bytes, expected at least {}",
+                size, MIN_ROM_SIZE
+            ),
+            RomError::NoHeader => write!(f, "ROM has no valid header"),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+#[derive(Clone)]
+pub struct Rom {
+    rom: Box<[u8]>,
+    /// Cartridge RAM, empty if the cartridge has none
+    sram: Box<[u8]>,
+    header: RomHeader,
+}
+
+impl Rom {
+    /// Parses the header of `rom_bytes` and sets up the mapping it describes
+    ///
+    /// A 512 byte copier header at the start of the file is skipped.
+    pub fn new(mut rom_bytes: Vec<u8>) -> Result<Rom, RomError> {
+        // Copier headers make the size uneven in kilobytes
+        if rom_bytes.len() % 1024 == COPIER_HEADER_SIZE {
+            info!("Skipping copier header");
+            rom_bytes.drain(..COPIER_HEADER_SIZE);
+        }
+        if rom_bytes.len() < MIN_ROM_SIZE {
+            return Err(RomError::TooSmall(rom_bytes.len()));
+        }
+
+        let header = RomHeader::parse(&rom_bytes)?;
+        info!(
+            "Loaded \"{}\" v1.{}, {:?} {:?}, {}K RAM",
+            header.title,
+            header.version,
+            header.region,
+            header.mapping,
+            header.ram_size / 1024
+        );
+        if let Some(coprocessor) = header.chipset.coprocessor {
+            warn!("Unsupported coprocessor {:?} is ignored", coprocessor);
+        }
+
+        Ok(Rom {
+            rom: rom_bytes.into_boxed_slice(),
+            sram: vec![0; header.ram_size].into_boxed_slice(),
+            header,
+        })
+    }
+
+    pub fn header(&self) -> &RomHeader {
+        &self.header
+    }
+
+    #[cfg(test)]
+    pub fn new_empty() -> Rom {
+        let mut rom = vec![0; 4194304];
+        // Map mode and reset vector of a LoROM header
+        rom[LOROM_HEADER + 0x15] = 0x20;
+        rom[LOROM_HEADER + 0x3D] = 0x80;
+        Rom::new(rom).unwrap()
+    }
+
+    pub fn sram(&self) -> &[u8] {
+        &self.sram
+    }
+
+    /// Replaces the contents of SRAM with `bytes`
+    ///
+    /// Bytes that don't fit are dropped and missing ones cleared.
+    pub fn load_sram(&mut self, bytes: &[u8]) {
+        if bytes.len() != self.sram.len() {
+            warn!(
+                "Loading {} bytes into {} bytes of SRAM",
+                bytes.len(),
+                self.sram.len()
+            );
+        }
+        let len = bytes.len().min(self.sram.len());
+        self.sram[..len].copy_from_slice(&bytes[..len]);
+        for b in &mut self.sram[len..] {
+            *b = 0;
+        }
+    }
+
+    /// Returns `true` if `bank_addr` in `bank` maps to SRAM
+    ///
+    /// Needed for the HiROM SRAM that is in the system area of banks $20-$3F.
+    pub fn maps_sram(&self, bank: usize, bank_addr: usize) -> bool {
+        self.sram_offset(bank, bank_addr).is_some()
+    }
+
+    /// Reads the ROM or SRAM byte mapped to `bank_addr` in `bank`
+    pub fn read8(&self, bank: usize, bank_addr: usize) -> u8 {
+        match self.sram_offset(bank, bank_addr) {
+            Some(offset) => self.sram[offset],
+            None => self.rom[self.offset(bank, bank_addr)],
+        }
+    }
+
+    pub fn write8(&mut self, bank: usize, bank_addr: usize, value: u8) {
+        match self.sram_offset(bank, bank_addr) {
+            Some(offset) => self.sram[offset] = value,
+            None => self.write_rom8(bank, bank_addr, value),
+        }
+    }
+
+    #[cfg(not(test))]
+    fn write_rom8(&mut self, bank: usize, bank_addr: usize, value: u8) {
+        warn!(
+            "Write value ${0:02X} to ROM at addr ${1:02X}:{2:04X}!",
+            value, bank, bank_addr
+        );
+    }
+
+    #[cfg(test)]
+    fn write_rom8(&mut self, bank: usize, bank_addr: usize, value: u8) {
+        let offset = self.offset(bank, bank_addr);
+        self.rom[offset] = value;
+    }
+
+    /// Returns the offset in SRAM that `bank_addr` in `bank` maps to, if any
+    ///
+    /// LoROM has SRAM in the lower halves of banks $70-$7D, HiROM in $6000-$7FFF of banks
+    /// $20-$3F. Both are mirrored in WS2 and SRAM smaller than the mapped area repeats.
+    fn sram_offset(&self, bank: usize, bank_addr: usize) -> Option<usize> {
+        if self.sram.is_empty() {
+            return None;
+        }
+        let bank = bank & 0x7F;
+        let offset = match self.header.mapping {
+            Mapping::LoRom | Mapping::ExLoRom
+                if (SRAM_LOROM_FIRST_BANK..=SRAM_LOROM_LAST_BANK).contains(&bank)
+                    && bank_addr < mmap::LOROM_FIRST =>
+            {
+                ((bank - SRAM_LOROM_FIRST_BANK) << 15) | bank_addr
+            }
+            Mapping::HiRom | Mapping::ExHiRom
+                if (SRAM_HIROM_FIRST_BANK..=SRAM_HIROM_LAST_BANK).contains(&bank)
+                    && (mmap::EXP_FIRST..=mmap::EXP_LAST).contains(&bank_addr) =>
+            {
+                ((bank - SRAM_HIROM_FIRST_BANK) << 13) | (bank_addr - mmap::EXP_FIRST)
+            }
+            _ => return None,
+        };
+        Some(offset % self.sram.len())
+    }
+
+    /// Returns the offset in ROM that `bank_addr` in `bank` maps to
+    ///
+    /// WS2 banks mirror the WS1 ones in plain mappings and ROMs smaller than the mapped area
+    /// repeat.
+    fn offset(&self, bank: usize, bank_addr: usize) -> usize {
+        // Extended mappings have the second 4M in WS1
+        let ex_base = if bank < mmap::WS2_SYSLR_FIRST_BANK {
+            EXTENDED_BASE
+        } else {
+            0
+        };
+        let lo_offset = ((bank & 0x7F) << 15) | (bank_addr & 0x7FFF);
+        let hi_offset = ((bank & 0x3F) << 16) | bank_addr;
+        let offset = match self.header.mapping {
+            Mapping::LoRom => lo_offset,
+            Mapping::HiRom => hi_offset,
+            Mapping::ExLoRom => ex_base + lo_offset,
+            Mapping::ExHiRom => ex_base + hi_offset,
+        };
+        offset % self.rom.len()
+    }
+}
+
+/// Smallest valid ROM, a single LoROM bank
+const MIN_ROM_SIZE: usize = 0x8000;
+/// Size of the header added by copier devices
+const COPIER_HEADER_SIZE: usize = 512;
+
+// Header locations, extended mappings have theirs in the second 4M
+const LOROM_HEADER: usize = 0x7FC0;
+const HIROM_HEADER: usize = 0xFFC0;
+const EXLOROM_HEADER: usize = EXTENDED_BASE + LOROM_HEADER;
+const EXHIROM_HEADER: usize = EXTENDED_BASE + HIROM_HEADER;
+
+/// Offset of the part of extended ROMs mapped to WS1
+const EXTENDED_BASE: usize = 0x400000;
+
+// Banks with SRAM
+const SRAM_LOROM_FIRST_BANK: usize = 0x70;
+const SRAM_LOROM_LAST_BANK: usize = 0x7D;
+const SRAM_HIROM_FIRST_BANK: usize = 0x20;
+const SRAM_HIROM_LAST_BANK: usize = 0x3F;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abus::ABus;
+
+    /// Builds a `size` byte ROM with a valid header at `header` that has `makeup`
+    fn rom_bytes(size: usize, header: usize, makeup: u8) -> Vec<u8> {
+        let mut rom = vec![0; size];
+        rom[header..header + 21].copy_from_slice(b"TEST                 ");
+        rom[header + 0x15] = makeup;
+        // Checksum complement
+        rom[header + 0x1C] = 0xFF;
+        rom[header + 0x1D] = 0xFF;
+        // Reset vector
+        rom[header + 0x3D] = 0x80;
+        rom
+    }
+
+    /// Builds a bus with a `size` byte ROM that has `makeup` in the header at `header` and
+    /// `markers` at their offsets
+    fn abus(size: usize, header: usize, makeup: u8, markers: &[(usize, u8)]) -> ABus {
+        let mut rom = rom_bytes(size, header, makeup);
+        for &(offset, value) in markers {
+            rom[offset] = value;
+        }
+        ABus::new(Rom::new(rom).unwrap())
+    }
+
+    /// Checks that each address in `mapped` reads the marker at its ROM offset
+    fn check_mapping(size: usize, header: usize, makeup: u8, mapped: &[(u32, usize)]) {
+        let markers: Vec<(usize, u8)> = mapped
+            .iter()
+            .enumerate()
+            .map(|(i, &(_, offset))| (offset, i as u8 + 1))
+            .collect();
+        let abus = abus(size, header, makeup, &markers);
+        for (&(addr, offset), &(_, value)) in mapped.iter().zip(markers.iter()) {
+            assert_eq!(
+                abus.cpu_peek8(addr),
+                value,
+                "${:06X} should map to ROM offset ${:06X}",
+                addr,
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn lorom_mapping() {
+        check_mapping(
+            0x400000,
+            LOROM_HEADER,
+            0x20,
+            &[
+                // WS1 system and LoROM banks
+                (0x008000, 0x000000),
+                (0x01C000, 0x00C000),
+                (0x3FFFFF, 0x1FFFFF),
+                // WS1 HiROM banks
+                (0x408000, 0x200000),
+                (0x410123, 0x208123),
+                (0x7DFFFF, 0x3EFFFF),
+                // WS2 system and LoROM banks
+                (0x808001, 0x000001),
+                (0xBF8000, 0x1F8000),
+                // WS2 HiROM banks
+                (0xC08002, 0x200002),
+                (0xFFFFFF, 0x3FFFFF),
+            ],
+        );
+    }
+
+    #[test]
+    fn fast_lorom_mapping() {
+        check_mapping(
+            0x100000,
+            LOROM_HEADER,
+            0x30,
+            &[(0x008000, 0x000000), (0x9FFFFF, 0x0FFFFF)],
+        );
+    }
+
+    #[test]
+    fn hirom_mapping() {
+        check_mapping(
+            0x400000,
+            HIROM_HEADER,
+            0x21,
+            &[
+                // WS1 system and LoROM banks
+                (0x008000, 0x008000),
+                (0x01FFFF, 0x01FFFF),
+                (0x3FC000, 0x3FC000),
+                // WS1 HiROM banks
+                (0x400000, 0x000000),
+                (0x5A1234, 0x1A1234),
+                (0x7DFFFF, 0x3DFFFF),
+                // WS2 system and LoROM banks
+                (0x808001, 0x008001),
+                (0xBFFFFF, 0x3FFFFF),
+                // WS2 HiROM banks
+                (0xC00001, 0x000001),
+                (0xE04000, 0x204000),
+                (0xFFFFFE, 0x3FFFFE),
+            ],
+        );
+    }
+
+    #[test]
+    fn exlorom_mapping() {
+        check_mapping(
+            0x7F0000,
+            EXLOROM_HEADER,
+            0x22,
+            &[
+                // WS1 system and LoROM banks
+                (0x008000, 0x400000),
+                (0x3FFFFF, 0x5FFFFF),
+                // WS1 HiROM banks
+                (0x408000, 0x600000),
+                (0x7DFFFF, 0x7EFFFF),
+                // WS2 system and LoROM banks
+                (0x808000, 0x000000),
+                (0xBFFFFF, 0x1FFFFF),
+                // WS2 HiROM banks
+                (0xC08000, 0x200000),
+                (0xFFFFFF, 0x3FFFFF),
+            ],
+        );
+    }
+
+    #[test]
+    fn exhirom_mapping() {
+        check_mapping(
+            0x800000,
+            EXHIROM_HEADER,
+            0x35,
+            &[
+                // WS1 system and LoROM banks
+                (0x008000, 0x408000),
+                (0x3FFFFF, 0x7FFFFF),
+                // WS1 HiROM banks
+                (0x400000, 0x400000),
+                (0x7D1234, 0x7D1234),
+                // WS2 system and LoROM banks
+                (0x808000, 0x008000),
+                (0xBFFFFF, 0x3FFFFF),
+                // WS2 HiROM banks
+                (0xC00000, 0x000000),
+                (0xFFFFFE, 0x3FFFFE),
+            ],
+        );
+    }
+
+    #[test]
+    fn wram_banks_are_not_rom() {
+        let mut abus = abus(0x400000, HIROM_HEADER, 0x21, &[(0x3E0000, 0xAA)]);
+        abus.cpu_write8(0x7E0000, 0x55);
+        assert_eq!(abus.cpu_peek8(0x7E0000), 0x55);
+        assert_eq!(abus.cpu_peek8(0x7F0000), 0x00);
+        assert_eq!(abus.cpu_peek8(0xFE0000), 0xAA);
+    }
+
+    #[test]
+    fn copier_header_is_skipped() {
+        let mut rom = vec![0xAA; COPIER_HEADER_SIZE];
+        rom.extend(rom_bytes(0x10000, HIROM_HEADER, 0x21));
+        let rom = Rom::new(rom).unwrap();
+        assert_eq!(rom.header().mapping, Mapping::HiRom);
+        assert_eq!(rom.header().title, "TEST");
+        assert_eq!(rom.read8(0xC0, 0x0000), 0x00);
+    }
+
+    #[test]
+    fn best_scoring_header_is_used() {
+        // LoROM location has a believable map mode but no valid complement or reset vector
+        let mut rom = rom_bytes(0x10000, HIROM_HEADER, 0x31);
+        rom[LOROM_HEADER + 0x15] = 0x20;
+        rom[HIROM_HEADER + 0x18] = 0x03;
+        rom[HIROM_HEADER + 0x19] = 0x01;
+        let header = Rom::new(rom).unwrap().header().clone();
+        assert_eq!(header.mapping, Mapping::HiRom);
+        assert!(header.fast);
+        assert_eq!(header.ram_size, 8 * 1024);
+        assert_eq!(header.region, Region::NorthAmerica);
+    }
+
+    #[test]
+    fn large_lorom_is_not_exlorom() {
+        // Data at the ExLoROM location looks like a header with the LoROM map mode
+        let mut rom = rom_bytes(0x600000, LOROM_HEADER, 0x20);
+        let ex_header = rom_bytes(0x8000, LOROM_HEADER, 0x20);
+        rom[EXLOROM_HEADER..EXLOROM_HEADER + 0x40]
+            .copy_from_slice(&ex_header[LOROM_HEADER..LOROM_HEADER + 0x40]);
+        let header = Rom::new(rom.clone()).unwrap().header().clone();
+        assert_eq!(header.mapping, Mapping::LoRom);
+
+        // Expanded LoROM is detected when there's no header at the LoROM location
+        rom[LOROM_HEADER..LOROM_HEADER + 0x40].copy_from_slice(&[0; 0x40]);
+        let header = Rom::new(rom).unwrap().header().clone();
+        assert_eq!(header.mapping, Mapping::ExLoRom);
+    }
+
+    #[test]
+    fn invalid_roms_are_errors() {
+        assert!(matches!(
+            Rom::new(vec![0; 0x4000]),
+            Err(RomError::TooSmall(0x4000))
+        ));
+        assert!(matches!(Rom::new(vec![0; 0x8000]), Err(RomError::NoHeader)));
+    }
+
+    #[test]
+    fn lorom_sram() {
+        let mut rom = rom_bytes(0x100000, LOROM_HEADER, 0x20);
+        // 2K of SRAM
+        rom[LOROM_HEADER + 0x18] = 0x01;
+        let mut abus = ABus::new(Rom::new(rom).unwrap());
+        abus.cpu_write8(0x700000, 0x12);
+        abus.cpu_write8(0x7007FF, 0x34);
+        assert_eq!(abus.cpu_peek8(0xF00000), 0x12);
+        // SRAM repeats over the bank
+        assert_eq!(abus.cpu_peek8(0x700800), 0x12);
+        assert_eq!(abus.cpu_peek8(0x7D7FFF), 0x34);
+        assert_eq!(abus.sram()[0x7FF], 0x34);
+        // Upper halves are still ROM
+        assert_eq!(abus.cpu_peek8(0x708000), 0x00);
+    }
+
+    #[test]
+    fn hirom_sram() {
+        let mut rom = rom_bytes(0x100000, HIROM_HEADER, 0x21);
+        // 8K of SRAM
+        rom[HIROM_HEADER + 0x18] = 0x03;
+        let mut abus = ABus::new(Rom::new(rom).unwrap());
+        let mut sram = vec![0; 0x2000];
+        sram[0x1FFF] = 0x56;
+        abus.load_sram(&sram);
+        assert_eq!(abus.cpu_peek8(0x207FFF), 0x56);
+        assert_eq!(abus.cpu_peek8(0xBF7FFF), 0x56);
+        abus.cpu_write8(0xA06000, 0x78);
+        assert_eq!(abus.cpu_peek8(0x206000), 0x78);
+        assert_eq!(abus.sram()[0], 0x78);
+    }
+}
@@ -1,30 +1,39 @@
 use crate::abus::ABus;
-use crate::apu::Apu;
 use crate::cpu::W65c816s;
+use crate::rom::{Rom, RomError, RomHeader};
 
 /// Abstraction around the actual emu implementation
 pub struct Snes {
     pub abus: ABus,
     pub cpu: W65c816s,
-    pub apu: Apu,
-    rom_bytes: Vec<u8>,
 }
 
 impl Snes {
     /// Initializes new instance with given ROM
-    pub fn new(rom_bytes: Vec<u8>) -> Snes {
-        let mut abus = ABus::new(rom_bytes.clone());
+    pub fn new(rom_bytes: Vec<u8>) -> Result<Snes, RomError> {
+        Ok(Snes::with_rom(Rom::new(rom_bytes)?))
+    }
+
+    fn with_rom(rom: Rom) -> Snes {
+        let abus = ABus::new(rom);
         Snes {
-            cpu: W65c816s::new(&mut abus),
+            cpu: W65c816s::new(&abus),
             abus,
-            apu: Apu::default(),
-            rom_bytes,
         }
     }
 
+    /// Returns the header of the loaded ROM
+    pub fn rom_header(&self) -> &RomHeader {
+        self.abus.rom().header()
+    }
+
     /// Performs a full reset (off and on again)
     pub fn reset(&mut self) {
-        *self = Snes::new(self.rom_bytes.clone());
+        // Battery backed SRAM survives resets as it's part of the cartridge
+        let rom = self.abus.rom().clone();
+        let strict_ppu_access = self.abus.strict_ppu_access();
+        *self = Snes::with_rom(rom);
+        self.abus.set_strict_ppu_access(strict_ppu_access);
     }
 
     /// Runs the hardware for given number of ticks and returns actual ticks emulated and wheter
@@ -38,22 +47,18 @@ impl Snes {
     where
         F: FnMut(&W65c816s, &mut ABus),
     {
-        let ticks_per_cycle = 8;
-        let target_cpu_cycles = clock_ticks / ticks_per_cycle; // SlowROM (?)
-        let mut cpu_cycles = 0;
+        let mut ticks = 0;
         let mut hit_breakpoint = false;
-        while cpu_cycles < target_cpu_cycles {
-            if cpu_cycles == 0 || self.cpu.current_address() != breakpoint {
+        while ticks < clock_ticks {
+            if ticks == 0 || self.cpu.current_address() != breakpoint {
                 disassemble_func(&self.cpu, &mut self.abus);
-                cpu_cycles += self.cpu.step(&mut self.abus) as u128;
-                let (_, apu_io) = self.apu.step(self.abus.apu_io());
-                self.abus.write_smp_io(apu_io);
+                ticks += self.step() as u128;
             } else {
                 hit_breakpoint = true;
                 break;
             }
         }
-        (cpu_cycles * ticks_per_cycle, hit_breakpoint) // SlowROM
+        (ticks, hit_breakpoint)
     }
 
     /// Runs the hardware for given number instructions
@@ -63,9 +68,43 @@ impl Snes {
     {
         for _ in 0..instructions {
             disassemble_func(&self.cpu, &mut self.abus);
-            self.cpu.step(&mut self.abus);
-            let (_, apu_io) = self.apu.step(self.abus.apu_io());
-            self.abus.write_smp_io(apu_io);
+            self.step();
+        }
+    }
+
+    /// Executes a single instruction, advances the rest of the hardware accordingly and returns
+    /// the number of master clock ticks emulated
+    fn step(&mut self) -> u32 {
+        let cycles = self.cpu.step(&mut self.abus) as u32;
+        // Internal cycles the CPU doesn't report as they happen are spent at the end of the
+        // instruction
+        for _ in self.abus.cpu_cycles()..cycles {
+            self.abus.cpu_idle();
         }
+        let ticks = self.abus.take_cpu_ticks() + self.abus.take_stall_ticks();
+        self.cpu.set_nmi(self.abus.nmi());
+        self.cpu.set_irq(self.abus.irq());
+        ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_step_ticks() {
+        let mut rom = vec![0; 0x8000];
+        // NOP at the reset vector of a LoROM header
+        rom[0x0000] = 0xEA;
+        rom[0x7FD5] = 0x20;
+        rom[0x7FFD] = 0x80;
+        let mut snes = Snes::new(rom).unwrap();
+        // Opcode fetch from SlowROM and an internal cycle
+        assert_eq!(snes.step(), 8 + 6);
+        assert_eq!(snes.cpu.current_address(), 0x008001);
+
+        snes.reset();
+        assert_eq!(snes.step(), 8 + 6);
     }
 }
@@ -89,6 +89,20 @@ impl Vram {
         }
     }
 
+    /// Ignores a write to VMDATAL, only updating the address like the hardware does
+    pub fn skip_vmdatal(&mut self) {
+        if let Byte::Low = self.vmain.byte {
+            self.increment();
+        }
+    }
+
+    /// Ignores a write to VMDATAH, only updating the address like the hardware does
+    pub fn skip_vmdatah(&mut self) {
+        if let Byte::High = self.vmain.byte {
+            self.increment();
+        }
+    }
+
     pub fn read_low(&mut self) -> u8 {
         let value = self.peek_low();
         if let Byte::Low = self.vmain.byte {